@@ -1,25 +1,101 @@
+use crate::auth::{self, AuthContext, AuthProvider};
+use crate::cache::{self, CachedToken, Snapshot};
+use crate::history;
+use crate::oidc::{self, LoginFlow, LoginNoticeFunction};
 use http::status::StatusCode;
 use http::Uri;
-use isahc::{auth::Authentication, prelude::*, HttpClient, Request};
+use isahc::config::CaCertificate;
+use isahc::{prelude::*, HttpClient, Request};
 use rpassword::prompt_password;
 use serde::{de, Deserialize, Serialize};
 use serde_json;
 use std::fmt;
-use std::io::{Read, Write};
 use std::ops::DerefMut;
-use std::process::{Command, Stdio};
-use std::sync::mpsc::channel;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
-use url::Url;
-use users::get_current_username;
-
+use std::thread;
+use std::time::Duration;
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+/// A logged-in (or about to be) drink server client. Clones share a token,
+/// so logging in once is enough for all of them
 pub struct API {
-  token: Arc<Mutex<Option<String>>>,
-  api_base_url: String,
+  token: Arc<Mutex<Option<CachedToken>>>,
+  config: APIConfig,
+  client: HttpClient,
   password_function: Arc<Mutex<Box<PasswordFunction>>>,
+  login_notice: Arc<Mutex<Box<LoginNoticeFunction>>>,
+  providers: Arc<Vec<Box<dyn AuthProvider>>>,
+}
+
+/// Where the drink server and SSO live, and who we log in as
+#[derive(Debug, Clone)]
+pub struct APIConfig {
+  pub api_base_url: String,
+  /// OIDC issuer, the `/protocol/openid-connect/...` endpoints hang off of this
+  pub oidc_issuer: String,
+  pub client_id: String,
+  /// Space-separated
+  pub scopes: String,
+  pub kerberos_realm: String,
+  /// Credentials cache for clink's own Kerberos tickets, used when the
+  /// default one has no ticket SSO will take. `None` uses the default one
+  /// for everything
+  pub kerberos_cache: Option<PathBuf>,
+  /// How to get a token when we don't have one
+  pub login: LoginFlow,
+  /// Token to use instead of logging in, with or without `Bearer `
+  pub token: Option<String>,
+  /// File holding a token to use instead of logging in, read whenever we need one
+  pub token_file: Option<PathBuf>,
+  /// Falls back to the current user if `None`
+  pub username: Option<String>,
+  /// Where to keep the token between runs, `None` keeps it in memory only
+  pub token_cache: Option<PathBuf>,
+  /// Where to keep the last full machine list, for completions
+  pub snapshot_cache: Option<PathBuf>,
+  /// Where successful drops are recorded, `None` to not record them
+  pub history: Option<PathBuf>,
+  /// Limit on a whole request, including the response body
+  pub timeout: Duration,
+  pub connect_timeout: Duration,
+  /// How many times to retry a GET that failed in a way that might not happen again
+  pub retries: u32,
+  /// e.g. `http://proxy.example.com:3128` or `socks5h://localhost:1080`
+  pub proxy: Option<String>,
+  /// PEM bundle to trust instead of the system's CA certificates
+  pub ca_cert: Option<PathBuf>,
+}
+
+impl Default for APIConfig {
+  fn default() -> Self {
+    APIConfig {
+      api_base_url: "https://drink.csh.rit.edu".to_string(),
+      oidc_issuer: "https://sso.csh.rit.edu/auth/realms/csh".to_string(),
+      client_id: "clidrink".to_string(),
+      scopes: "openid profile drink_balance".to_string(),
+      kerberos_realm: "CSH.RIT.EDU".to_string(),
+      kerberos_cache: cache::ccache_path("prod"),
+      login: LoginFlow::Kerberos,
+      token: None,
+      token_file: None,
+      username: None,
+      token_cache: cache::token_path("prod"),
+      snapshot_cache: cache::snapshot_path("prod"),
+      history: history::path("prod"),
+      timeout: Duration::from_secs(30),
+      connect_timeout: Duration::from_secs(10),
+      retries: 3,
+      proxy: None,
+      ca_cert: None,
+    }
+  }
 }
 
+/// Each variant has its own exit code, see [`APIError::exit_code`]
 #[derive(Debug)]
 pub enum APIError {
   Unauthorized,
@@ -27,7 +103,31 @@ pub enum APIError {
   HTTPError(http::Error),
   IsahcError(isahc::Error),
   ServerError(Option<Uri>, String),
+  /// A 502, 503 or 504, the server or something in front of it is down
+  ServerUnavailable(Option<Uri>, String),
+  InsufficientCredits(String),
+  MachineOffline(String),
+  /// The slot is empty or has been marked inactive
+  SlotUnavailable(String),
+  NotFound(String),
+  Forbidden(String),
+  /// How long the server asked us to wait, if it said
+  RateLimited(Option<Duration>),
+  KerberosMissing(String),
+  SsoFailure(String),
   LoginAborted,
+  /// The user said no when asked to confirm, or picked nothing
+  Aborted,
+  ConfigError(String),
+  /// The local drop history couldn't be read
+  HistoryError(String),
+  /// An item can't be deleted while it's in a slot
+  InUse(String),
+  /// Asked to work offline before any machine list was saved
+  NoSnapshot,
+  /// Something that has to be confirmed couldn't be, since no one's at a
+  /// terminal to answer and `--yes` wasn't passed
+  Unconfirmed(String),
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -40,41 +140,91 @@ struct MessageResponse {
   message: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// What the drink server says is in its machines
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DrinkList {
   pub machines: Vec<Machine>,
   pub message: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Machine {
+  /// e.g. "Big Drink"
   pub display_name: String,
   pub id: u64,
   pub is_online: bool,
+  /// e.g. "bigdrink", what the API and commands take
   pub name: String,
   pub slots: Vec<Slot>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Slot {
+  /// Inactive slots can't be dropped from, whatever's in them
   pub active: bool,
+  /// How many are left, for machines that can tell
   pub count: Option<u64>,
   pub empty: bool,
   pub item: Item,
+  /// The id of the machine the slot is in
   pub machine: u64,
   pub number: u8,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Item {
   pub id: u64,
   pub name: String,
+  /// In credits
   pub price: u64,
 }
 
+/// Changes to make to a slot, anything `None` is left alone
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SlotUpdate {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub item_id: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub active: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub count: Option<u64>,
+}
+
+/// Changes to make to an item, anything `None` is left alone
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ItemUpdate {
+  pub id: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub price: Option<u64>,
+}
+
 #[derive(Deserialize, Debug, Clone)]
-struct User {
-  preferred_username: String,
+struct ItemsResponse {
+  items: Vec<Item>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct NewItemRequest<'a> {
+  name: &'a str,
+  price: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ItemResponse {
+  item: Item,
+}
+
+/// Who the SSO says we are
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct User {
+  pub preferred_username: String,
+  /// Full name
+  pub name: Option<String>,
+  pub email: Option<String>,
+  #[serde(default)]
+  pub groups: Vec<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -109,6 +259,13 @@ struct DropRequest {
   slot: u8,
 }
 
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug, Clone)]
+struct SetCreditsRequest<'a> {
+  uid: &'a str,
+  drinkBalance: i64,
+}
+
 #[allow(non_snake_case)]
 #[derive(Deserialize, Debug, Clone)]
 struct DropResponse {
@@ -118,6 +275,85 @@ struct DropResponse {
 
 impl std::error::Error for APIError {}
 
+impl APIError {
+  /// Builds the most specific error we can from a failed response
+  fn from_response(
+    status: StatusCode,
+    uri: Option<Uri>,
+    retry_after: Option<Duration>,
+    message: String,
+  ) -> APIError {
+    let lowercase = message.to_lowercase();
+    let mentions = |words: &[&str]| words.iter().any(|word| lowercase.contains(word));
+    let is_drop = uri
+      .as_ref()
+      .is_some_and(|uri| uri.path().ends_with("/drinks/drop"));
+    match status {
+      StatusCode::UNAUTHORIZED => APIError::Unauthorized,
+      StatusCode::PAYMENT_REQUIRED => APIError::InsufficientCredits(message),
+      StatusCode::FORBIDDEN => APIError::Forbidden(message),
+      StatusCode::NOT_FOUND => APIError::NotFound(message),
+      StatusCode::TOO_MANY_REQUESTS => APIError::RateLimited(retry_after),
+      StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => {
+        APIError::ServerUnavailable(uri, message)
+      }
+      // The drink server answers most failed drops with a 400, so go by what it said
+      _ if status.is_client_error() && is_drop => {
+        if mentions(&["sufficient drink credits"]) {
+          APIError::InsufficientCredits(message)
+        } else if mentions(&["could not contact drink machine"]) {
+          APIError::MachineOffline(message)
+        } else if mentions(&["slot is empty", "slot is not active"]) {
+          APIError::SlotUnavailable(message)
+        } else if mentions(&["invalid machine name", "invalid slot"]) {
+          APIError::NotFound(message)
+        } else {
+          APIError::ServerError(uri, message)
+        }
+      }
+      _ => APIError::ServerError(uri, message),
+    }
+  }
+
+  /// Whether this looks like the drink server or SSO being down or out of
+  /// reach, rather than something being wrong with the request
+  pub fn is_unreachable(&self) -> bool {
+    match self {
+      APIError::IsahcError(err) => err.is_network() || err.is_timeout(),
+      APIError::ServerUnavailable(_, _) | APIError::SsoFailure(_) => true,
+      _ => false,
+    }
+  }
+
+  /// What `clink` exits with when this error ends the program.
+  /// 1 is left for anything without a code of its own and 2 for usage errors
+  pub fn exit_code(&self) -> u8 {
+    match self {
+      APIError::LoginAborted => 0,
+      APIError::Unconfirmed(_) => 2,
+      APIError::Unauthorized => 3,
+      APIError::InsufficientCredits(_) => 4,
+      APIError::MachineOffline(_) => 5,
+      APIError::SlotUnavailable(_) => 6,
+      APIError::NotFound(_) => 7,
+      APIError::Forbidden(_) => 8,
+      APIError::RateLimited(_) => 9,
+      APIError::KerberosMissing(_) => 10,
+      APIError::SsoFailure(_) => 11,
+      APIError::ConfigError(_) => 12,
+      APIError::InUse(_) => 13,
+      APIError::NoSnapshot => 14,
+      APIError::Aborted => 15,
+      APIError::BadFormat
+      | APIError::HTTPError(_)
+      | APIError::IsahcError(_)
+      | APIError::ServerError(_, _)
+      | APIError::ServerUnavailable(_, _)
+      | APIError::HistoryError(_) => 1,
+    }
+  }
+}
+
 impl fmt::Display for APIError {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
@@ -135,19 +371,114 @@ impl fmt::Display for APIError {
         },
         message
       ),
+      APIError::ServerUnavailable(path, message) => write!(
+        f,
+        "ServerUnavailable for {}: {} (Try again later)",
+        match path {
+          Some(ref uri) => uri.to_string(),
+          None => "<unknown>".to_string(),
+        },
+        message
+      ),
       APIError::HTTPError(err) => write!(f, "HTTPError: {}", err),
       APIError::IsahcError(err) => write!(f, "IsahcError: {}", err),
+      APIError::InsufficientCredits(message) => write!(
+        f,
+        "InsufficientCredits: {} (Check your balance with `clink credits`)",
+        message
+      ),
+      APIError::MachineOffline(message) => write!(
+        f,
+        "MachineOffline: {} (Try another machine, `clink list` shows which are up)",
+        message
+      ),
+      APIError::SlotUnavailable(message) => write!(
+        f,
+        "SlotUnavailable: {} (Pick a slot that isn't empty or disabled)",
+        message
+      ),
+      APIError::NotFound(message) => write!(f, "NotFound: {}", message),
+      APIError::Unconfirmed(question) => write!(
+        f,
+        "Unconfirmed: {} (Pass --yes to go ahead without being asked)",
+        question
+      ),
+      APIError::Forbidden(message) => write!(
+        f,
+        "Forbidden: {} (Your account isn't allowed to do that)",
+        message
+      ),
+      APIError::RateLimited(Some(retry_after)) => write!(
+        f,
+        "RateLimited (Try again in {} seconds)",
+        retry_after.as_secs()
+      ),
+      APIError::RateLimited(None) => write!(f, "RateLimited (Try again in a bit)"),
+      APIError::KerberosMissing(message) => write!(
+        f,
+        "KerberosMissing: {} (Install Kerberos or log in on a user machine)",
+        message
+      ),
+      APIError::SsoFailure(message) => write!(
+        f,
+        "SsoFailure: {} (Single sign-on is having trouble, try again later)",
+        message
+      ),
       APIError::LoginAborted => write!(f, "LoginAborted"),
+      APIError::Aborted => write!(f, "Aborted"),
+      APIError::ConfigError(message) => write!(f, "ConfigError: {}", message),
+      APIError::HistoryError(message) => write!(f, "HistoryError: {}", message),
+      APIError::InUse(message) => write!(
+        f,
+        "InUse: {} (Put something else in those slots first with `clink admin slot`)",
+        message
+      ),
+      APIError::NoSnapshot => write!(
+        f,
+        "NoSnapshot: No machine list saved yet (Run `clink list` while online first)"
+      ),
     }
   }
 }
 
+/// Longest we'll wait before retrying, whatever the server asks for
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Delay before retry number `attempt` (from 0): exponential, starting at
+/// half a second, with jitter so a crowd of clinks don't all retry at once.
+/// Never more than `MAX_RETRY_DELAY`
+fn backoff(attempt: u32) -> Duration {
+  let ceiling = Duration::from_millis(500) * 2u32.saturating_pow(attempt.min(16));
+  // Anywhere from half the ceiling up to it
+  let jitter = (Uuid::new_v4().as_u128() % 1000) as u32;
+  (ceiling / 2 + ceiling / 2 * jitter / 1000).min(MAX_RETRY_DELAY)
+}
+
+/// How long a 429 or 503 response asked us to wait, if it said, either in
+/// seconds or as an HTTP date
+fn retry_after<T>(response: &http::Response<T>) -> Option<Duration> {
+  let value = response.headers().get("Retry-After")?.to_str().ok()?.trim();
+  if let Ok(seconds) = value.parse::<u64>() {
+    return Some(Duration::from_secs(seconds));
+  }
+  let date = PrimitiveDateTime::parse(
+    value,
+    format_description!(
+      "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+    ),
+  )
+  .ok()?
+  .assume_utc();
+  // A date that's already passed means we can go right away
+  Some(Duration::from_secs(
+    (date.unix_timestamp() - cache::now() as i64).max(0) as u64,
+  ))
+}
+
 impl Default for API {
   fn default() -> Self {
-    Self::new(
-      "https://drink.csh.rit.edu".to_string(),
-      Box::new(API::default_password_prompt),
-    )
+    // panic rationale: the default config has nothing that can fail to apply
+    Self::new(APIConfig::default(), Box::new(API::default_password_prompt)).unwrap()
   }
 }
 
@@ -156,18 +487,22 @@ enum APIBody<T: Serialize> {
   NoBody,
 }
 
-type TryPasswordFn = dyn Fn(String) -> Result<PasswordResult, APIError> + Send + 'static;
-type PasswordFunction = dyn Fn(String, Box<TryPasswordFn>) + Send + 'static;
+/// Tries logging in with a password, can be called again if it didn't work
+pub type TryPasswordFn = dyn Fn(String) -> Result<PasswordResult, APIError> + Send + 'static;
+/// Asks for the password of the user it's given and passes it to the
+/// `TryPasswordFn` until that succeeds or it gives up
+pub type PasswordFunction = dyn Fn(String, Box<TryPasswordFn>) + Send + 'static;
 
+/// How a password attempt went, `message` is fit to show the user
 pub struct PasswordResult {
   pub message: String,
   pub success: bool,
 }
 
-impl<T: Serialize> From<APIBody<T>> for isahc::Body {
-  fn from(body: APIBody<T>) -> Self {
+impl<T: Serialize> From<&APIBody<T>> for isahc::Body {
+  fn from(body: &APIBody<T>) -> Self {
     match body {
-      APIBody::Json(value) => serde_json::to_string(&value).unwrap().into(),
+      APIBody::Json(value) => serde_json::to_string(value).unwrap().into(),
       APIBody::NoBody => ().into(),
     }
   }
@@ -177,53 +512,135 @@ impl Clone for API {
   fn clone(&self) -> Self {
     Self {
       token: Arc::clone(&self.token),
-      api_base_url: self.api_base_url.clone(),
+      config: self.config.clone(),
+      client: self.client.clone(),
       password_function: Arc::clone(&self.password_function),
+      login_notice: Arc::clone(&self.login_notice),
+      providers: Arc::clone(&self.providers),
     }
   }
 }
 
 impl API {
-  pub fn new(api_base_url: String, password_function: Box<PasswordFunction>) -> API {
+  /// Logs in however `config` says to, see [`auth::default_providers`]
+  pub fn new(config: APIConfig, password_function: Box<PasswordFunction>) -> Result<API, APIError> {
     // We should find a way to spin this off in a thread
     // api.get_token().ok();
-    API {
+    let providers = auth::default_providers(&config);
+    let mut api = API::with_providers(config, providers)?;
+    api.set_password_prompt(password_function);
+    Ok(api)
+  }
+
+  /// Gets tokens from `providers`, tried in order, when there isn't a
+  /// usable one cached
+  pub fn with_providers(
+    config: APIConfig,
+    providers: Vec<Box<dyn AuthProvider>>,
+  ) -> Result<API, APIError> {
+    let client = API::build_client(&config)?;
+    Ok(API {
       token: Arc::new(Mutex::new(None)),
-      api_base_url,
-      password_function: Arc::new(Mutex::new(password_function)),
+      config,
+      client,
+      password_function: Arc::new(Mutex::new(Box::new(API::default_password_prompt))),
+      login_notice: Arc::new(Mutex::new(Box::new(API::default_login_notice))),
+      providers: Arc::new(providers),
+    })
+  }
+
+  /// One client for everything, so connections get reused between requests
+  fn build_client(config: &APIConfig) -> Result<HttpClient, APIError> {
+    let mut builder = HttpClient::builder()
+      .timeout(config.timeout)
+      .connect_timeout(config.connect_timeout)
+      .default_header("User-Agent", concat!("clink/", env!("CARGO_PKG_VERSION")));
+    if let Some(proxy) = &config.proxy {
+      let proxy = proxy
+        .parse::<Uri>()
+        .map_err(|err| APIError::ConfigError(format!("Bad proxy URL {}: {}", proxy, err)))?;
+      builder = builder.proxy(Some(proxy));
+    }
+    if let Some(ca_cert) = &config.ca_cert {
+      builder = builder.ssl_ca_certificate(CaCertificate::file(ca_cert));
     }
+    builder.build().map_err(APIError::IsahcError)
   }
+  /// `request` may be called more than once, since we retry with a fresh
+  /// token if the server rejects the one we had cached
   fn authenticated_request<O, I>(
     &self,
-    builder: http::request::Builder,
+    request: impl Fn() -> http::request::Builder,
     input: APIBody<I>,
   ) -> Result<O, APIError>
   where
     I: Serialize,
     O: de::DeserializeOwned,
   {
-    let client = HttpClient::new().map_err(APIError::IsahcError)?;
-    let token = self.get_token()?;
-    let builder = builder
-      .header("Authorization", token)
-      .header("Accept", "application/json");
-    let builder = match input {
-      APIBody::Json(_) => builder.header("Content-Type", "application/json"),
-      APIBody::NoBody => builder,
+    // Only requests that can't change anything are safe to repeat. Retrying
+    // a drop could charge someone twice if the first one got through
+    let idempotent = request().method_ref() == Some(&http::Method::GET);
+    let uri = request().uri_ref().cloned();
+    let mut attempt = 0;
+    let mut retried = false;
+    let mut response = loop {
+      let token = self.get_token()?;
+      let builder = request()
+        .header("Authorization", token)
+        .header("Accept", "application/json");
+      let builder = match input {
+        APIBody::Json(_) => builder.header("Content-Type", "application/json"),
+        APIBody::NoBody => builder,
+      };
+      let can_retry = idempotent && attempt < self.config.retries;
+      let response = match self
+        .client
+        .send(builder.body(&input).map_err(APIError::HTTPError)?)
+      {
+        Ok(response) => response,
+        Err(err) if can_retry && (err.is_network() || err.is_timeout()) => {
+          thread::sleep(backoff(attempt));
+          attempt += 1;
+          continue;
+        }
+        Err(err) => return Err(APIError::IsahcError(err)),
+      };
+      match response.status() {
+        StatusCode::UNAUTHORIZED if !retried => {
+          // Our token was probably revoked or expired early, get another
+          self.expire_token();
+          retried = true;
+        }
+        StatusCode::TOO_MANY_REQUESTS
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT
+          if can_retry =>
+        {
+          let delay = retry_after(&response).unwrap_or_else(|| backoff(attempt));
+          // Better to tell the user than to sit there silently for ages
+          if delay > MAX_RETRY_DELAY {
+            break response;
+          }
+          thread::sleep(delay);
+          attempt += 1;
+        }
+        _ => break response,
+      }
     };
-    let mut response = client
-      .send(builder.body(input).map_err(APIError::HTTPError)?)
-      .map_err(APIError::IsahcError)?;
     match response.status() {
       StatusCode::OK => match response.json::<O>() {
         Ok(value) => Ok(value),
         Err(_) => Err(APIError::BadFormat),
       },
-      _ => {
+      status => {
+        let retry_after = retry_after(&response);
         let text = response.text().map_err(|_| APIError::BadFormat)?;
         let text_ref = &text;
-        Err(APIError::ServerError(
-          response.effective_uri().cloned(),
+        Err(APIError::from_response(
+          status,
+          uri,
+          retry_after,
           serde_json::from_str::<ErrorResponse>(&text)
             .map(|body| body.error)
             .or_else(move |_| {
@@ -235,46 +652,128 @@ impl API {
     }
   }
   pub fn drop(&self, machine: String, slot: u8) -> Result<i64, APIError> {
+    let uri = format!("{}/drinks/drop", self.config.api_base_url);
     self
       .authenticated_request::<DropResponse, _>(
-        Request::post(format!("{}/drinks/drop", self.api_base_url)),
+        || Request::post(&uri),
         APIBody::Json(DropRequest { machine, slot }),
       )
       .map(|drop| drop.drinkBalance)
   }
 
-  fn take_token(&self, token: &mut Option<String>) -> Result<String, APIError> {
-    match token {
-      Some(token) => Ok(token.to_string()),
-      None => {
-        let response = Request::get("https://sso.csh.rit.edu/auth/realms/csh/protocol/openid-connect/auth?client_id=clidrink&redirect_uri=drink%3A%2F%2Fcallback&response_type=token%20id_token&scope=openid%20profile%20drink_balance&state=&nonce=")
-          .authentication(Authentication::negotiate())
-          .body(()).map_err(APIError::HTTPError)?.send().map_err(APIError::IsahcError)?;
-        let location = match response.headers().get("Location") {
-          Some(location) => location,
-          None => {
-            self.login()?;
-            return self.take_token(token);
-          }
-        };
-        let url = Url::parse(
-          &location
-            .to_str()
-            .map_err(|_| APIError::BadFormat)?
-            .replace('#', "?"),
-        )
-        .map_err(|_| APIError::BadFormat)?;
-
-        for (key, value) in url.query_pairs() {
-          if key == "access_token" {
-            let value = format!("Bearer {}", value);
-            *token = Some(value.clone());
-            return Ok(value);
-          }
-        }
-        Err(APIError::BadFormat)
+  /// Every item the drink server knows about, stocked or not
+  pub fn list_items(&self) -> Result<Vec<Item>, APIError> {
+    let uri = format!("{}/drinks/items", self.config.api_base_url);
+    self
+      .authenticated_request::<ItemsResponse, _>(
+        || Request::get(&uri),
+        APIBody::NoBody as APIBody<serde_json::Value>,
+      )
+      .map(|response| response.items)
+  }
+
+  /// Adds an item to the catalog, drink admins only
+  pub fn create_item(&self, name: &str, price: u64) -> Result<Item, APIError> {
+    let uri = format!("{}/drinks/items", self.config.api_base_url);
+    self
+      .authenticated_request::<ItemResponse, _>(
+        || Request::post(&uri),
+        APIBody::Json(NewItemRequest { name, price }),
+      )
+      .map(|response| response.item)
+  }
+
+  /// Renames or reprices an item, drink admins only
+  pub fn update_item(&self, update: &ItemUpdate) -> Result<(), APIError> {
+    let uri = format!("{}/drinks/items", self.config.api_base_url);
+    self
+      .authenticated_request::<serde_json::Value, _>(|| Request::put(&uri), APIBody::Json(update))
+      .map(|_| ())
+  }
+
+  /// Removes an item from the catalog, drink admins only
+  pub fn delete_item(&self, id: u64) -> Result<(), APIError> {
+    let uri = format!("{}/drinks/items/{}", self.config.api_base_url, id);
+    self
+      .authenticated_request::<serde_json::Value, _>(
+        || Request::delete(&uri),
+        APIBody::NoBody as APIBody<serde_json::Value>,
+      )
+      .map(|_| ())
+  }
+
+  /// Changes what's in a slot, drink admins only
+  pub fn update_slot(&self, machine: &str, slot: u8, update: &SlotUpdate) -> Result<(), APIError> {
+    let mut uri = url::Url::parse(&format!("{}/drinks/slots", self.config.api_base_url))
+      .map_err(|err| APIError::ConfigError(format!("Bad API URL: {}", err)))?;
+    uri
+      .path_segments_mut()
+      .map_err(|_| APIError::ConfigError("Bad API URL".to_string()))?
+      .push(machine)
+      .push(&slot.to_string());
+    let uri = uri.to_string();
+    self
+      .authenticated_request::<serde_json::Value, _>(|| Request::put(&uri), APIBody::Json(update))
+      .map(|_| ())
+  }
+
+  fn take_token(&self, token: &mut Option<CachedToken>) -> Result<String, APIError> {
+    // A token we were handed beats whoever last logged in, so while there is
+    // one, the token cache and its refresh token are left alone
+    let use_cache = !self.providers.iter().any(|provider| provider.is_given());
+    if use_cache && token.as_ref().map(CachedToken::is_expired).unwrap_or(true) {
+      // Maybe an earlier clink left us something usable
+      let cached = self
+        .config
+        .token_cache
+        .as_deref()
+        .and_then(cache::read::<CachedToken>)
+        .filter(|cached| !cached.is_expired() || cached.can_refresh());
+      if cached.is_some() {
+        *token = cached;
+      }
+    }
+    if let Some(current) = token.as_ref().filter(|current| !current.is_expired()) {
+      return Ok(current.token.clone());
+    }
+    // Only log in again if there's no refresh token or SSO won't take it
+    let refreshed = match token
+      .as_ref()
+      .filter(|current| use_cache && current.can_refresh())
+    {
+      Some(current) => oidc::refresh(&self.client, &self.config, current)?,
+      None => None,
+    };
+    let (fresh, cacheable) = match refreshed {
+      Some(fresh) => (fresh, true),
+      None => self.fetch_token()?,
+    };
+    if let (Some(path), true) = (&self.config.token_cache, cacheable) {
+      // The cache only saves time, so failing to write it isn't fatal
+      cache::write(path, &fresh).ok();
+    }
+    let value = fresh.token.clone();
+    *token = Some(fresh);
+    Ok(value)
+  }
+
+  /// Gets a brand new token from the first provider that has one, and whether
+  /// it's worth caching
+  fn fetch_token(&self) -> Result<(CachedToken, bool), APIError> {
+    let password = self.password_function.lock().unwrap();
+    let notice = self.login_notice.lock().unwrap();
+    let context = AuthContext {
+      client: &self.client,
+      config: &self.config,
+      password: password.as_ref(),
+      notice: notice.as_ref(),
+    };
+    for provider in self.providers.iter() {
+      if let Some(token) = provider.token(&context)? {
+        return Ok((token, provider.cacheable()));
       }
     }
+    Err(APIError::Unauthorized)
   }
 
   pub fn get_token(&self) -> Result<String, APIError> {
@@ -282,6 +781,32 @@ impl API {
     self.take_token(token.deref_mut())
   }
 
+  /// Treats the current token as expired, so the next request refreshes it
+  /// if it can, or logs in again if it can't
+  fn expire_token(&self) {
+    let mut token = self.token.lock().unwrap();
+    match token.as_mut() {
+      Some(current) if current.refresh_token.is_some() => {
+        current.expires_at = Some(0);
+        if let Some(path) = &self.config.token_cache {
+          cache::write(path, current).ok();
+        }
+      }
+      _ => {
+        drop(token);
+        self.invalidate_token();
+      }
+    }
+  }
+
+  /// Forgets the current token, both in memory and on disk
+  pub fn invalidate_token(&self) {
+    *self.token.lock().unwrap() = None;
+    if let Some(path) = &self.config.token_cache {
+      cache::remove(path);
+    }
+  }
+
   pub fn default_password_prompt(username: String, try_password: Box<TryPasswordFn>) {
     loop {
       let password = prompt_password(format!("Password for {username}: ")).unwrap();
@@ -309,79 +834,183 @@ impl API {
     self.password_function = Arc::new(Mutex::new(prompt));
   }
 
-  fn login(&self) -> Result<(), APIError> {
-    // Get credentials
-    let username: String = std::env::var("CLINK_USERNAME")
-      .ok()
-      .or_else(|| get_current_username().and_then(|username| username.into_string().ok()))
-      .or_else(|| std::env::var("USER").ok())
-      .expect("Couldn't determine username");
-
-    let password_function = self.password_function.lock().unwrap();
-    let (tx_password, rx_password) = channel();
-    // Get password
-    (password_function)(
-      username.clone(),
-      Box::new(move |password| {
-        // Start kinit, ready to get password from pipe
-        let mut process = Command::new("kinit")
-          .arg(format!("{}@CSH.RIT.EDU", username))
-          .stdin(Stdio::piped())
-          .stdout(Stdio::null())
-          .stderr(Stdio::piped())
-          .spawn()
-          .unwrap();
-        process
-          .stdin
-          .as_ref()
-          .unwrap()
-          .write_all(password.as_bytes())
-          .unwrap();
-        let success = process.wait().unwrap().success();
-        if success {
-          tx_password.send(()).unwrap();
-        }
-        let mut output = "".to_string();
-        process.stderr.unwrap().read_to_string(&mut output).unwrap();
-        Ok(PasswordResult {
-          success,
-          message: output,
-        })
-      }),
+  pub fn default_login_notice(message: String) {
+    eprintln!("{}", message);
+  }
+
+  /// Replaces how the device and PKCE logins tell the user what to do
+  pub fn set_login_notice(&mut self, notice: Box<LoginNoticeFunction>) {
+    self.login_notice = Arc::new(Mutex::new(notice));
+  }
+
+  pub fn get_user_info(&self) -> Result<User, APIError> {
+    let uri = format!(
+      "{}/protocol/openid-connect/userinfo",
+      self.config.oidc_issuer
     );
-    match rx_password.recv() {
-      Ok(_) => Ok(()),
-      Err(_) => Err(APIError::LoginAborted),
-    }
+    self.authenticated_request(
+      || Request::get(&uri),
+      APIBody::NoBody as APIBody<serde_json::Value>,
+    )
   }
 
   pub fn get_credits(&self) -> Result<i64, APIError> {
-    // Can also be used to get other user information
-    let user: User = self.authenticated_request(
-      Request::get("https://sso.csh.rit.edu/auth/realms/csh/protocol/openid-connect/userinfo"),
-      APIBody::NoBody as APIBody<serde_json::Value>,
-    )?;
+    let user = self.get_user_info()?;
+    self.get_credits_for(&user.preferred_username)
+  }
+
+  /// Anyone's balance, though only drink admins may look up other people
+  pub fn get_credits_for(&self, uid: &str) -> Result<i64, APIError> {
+    let uri = format!(
+      "{}/users/credits?uid={}",
+      self.config.api_base_url,
+      url::form_urlencoded::byte_serialize(uid.as_bytes()).collect::<String>()
+    );
     let credit_response: CreditResponse = self.authenticated_request(
-      Request::get(format!(
-        "{}/users/credits?uid={}",
-        self.api_base_url, user.preferred_username
-      )),
+      || Request::get(&uri),
       APIBody::NoBody as APIBody<serde_json::Value>,
     )?;
     Ok(credit_response.user.drinkBalance)
   }
 
+  /// Overwrites `uid`'s balance, drink admins only
+  pub fn set_credits(&self, uid: &str, balance: i64) -> Result<(), APIError> {
+    let uri = format!("{}/users/credits", self.config.api_base_url);
+    self
+      .authenticated_request::<serde_json::Value, _>(
+        || Request::put(&uri),
+        APIBody::Json(SetCreditsRequest {
+          uid,
+          drinkBalance: balance,
+        }),
+      )
+      .map(|_| ())
+  }
+
   pub fn get_status_for_machine(&self, machine: Option<&str>) -> Result<DrinkList, APIError> {
-    self.authenticated_request(
-      Request::get(format!(
-        "{}/drinks{}",
-        self.api_base_url,
-        match machine {
-          Some(machine) => format!("?machine={}", machine),
-          None => "".to_string(),
-        }
-      )),
+    let uri = format!(
+      "{}/drinks{}",
+      self.config.api_base_url,
+      match machine {
+        Some(machine) => format!(
+          "?machine={}",
+          url::form_urlencoded::byte_serialize(machine.as_bytes()).collect::<String>()
+        ),
+        None => "".to_string(),
+      }
+    );
+    let drinks: DrinkList = self.authenticated_request(
+      || Request::get(&uri),
       APIBody::NoBody as APIBody<serde_json::Value>,
-    )
+    )?;
+    if let (None, Some(path)) = (machine, &self.config.snapshot_cache) {
+      // Like the token cache, this only saves time later
+      cache::write(path, &Snapshot::new(drinks.clone())).ok();
+    }
+    Ok(drinks)
+  }
+
+  /// The last full machine list we fetched, however old
+  pub fn cached_status(&self) -> Option<Snapshot> {
+    cache::read(self.config.snapshot_cache.as_deref()?)
+  }
+
+  /// Where clink keeps its own Kerberos tickets, see [`APIConfig::kerberos_cache`]
+  pub fn kerberos_cache(&self) -> Option<PathBuf> {
+    self.config.kerberos_cache.clone()
+  }
+
+  /// Where successful drops are recorded, see [`APIConfig::history`]
+  pub fn history_path(&self) -> Option<PathBuf> {
+    self.config.history.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use time::OffsetDateTime;
+
+  #[test]
+  fn backoff_grows_with_jitter() {
+    for attempt in 0..4 {
+      let ceiling = Duration::from_millis(500) * 2u32.pow(attempt);
+      let delay = backoff(attempt);
+      assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?}", delay);
+    }
+    assert_eq!(backoff(10), MAX_RETRY_DELAY);
+    assert_eq!(backoff(u32::MAX), MAX_RETRY_DELAY);
+  }
+
+  #[test]
+  fn reads_both_kinds_of_retry_after() {
+    let response = |value: &str| {
+      http::Response::builder()
+        .header("Retry-After", value)
+        .body(())
+        .unwrap()
+    };
+    assert_eq!(
+      retry_after(&response("120")),
+      Some(Duration::from_secs(120))
+    );
+    assert_eq!(
+      retry_after(&response("Sun, 06 Nov 1994 08:49:37 GMT")),
+      Some(Duration::ZERO)
+    );
+    let later = OffsetDateTime::now_utc() + time::Duration::minutes(5);
+    let header = later
+      .format(format_description!(
+        "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+      ))
+      .unwrap();
+    let delay = retry_after(&response(&header)).unwrap();
+    assert!(delay > Duration::from_secs(290) && delay <= Duration::from_secs(300));
+    assert_eq!(retry_after(&response("soon")), None);
+  }
+
+  #[test]
+  fn only_reads_messages_from_failed_drops() {
+    let error = |status: u16, path: &str, message: &str| {
+      let uri = format!("https://drink.csh.rit.edu{}", path).parse().ok();
+      let status = StatusCode::from_u16(status).unwrap();
+      APIError::from_response(status, uri, None, message.to_string())
+    };
+    assert!(matches!(
+      error(400, "/drinks/drop", "The requested slot is empty!"),
+      APIError::SlotUnavailable(_)
+    ));
+    assert!(matches!(
+      error(
+        400,
+        "/drinks/drop",
+        "Could not contact drink machine for drop!"
+      ),
+      APIError::MachineOffline(_)
+    ));
+    assert!(matches!(
+      error(500, "/drinks/drop", "Couldn't update balance"),
+      APIError::ServerError(_, _)
+    ));
+    assert!(matches!(
+      error(400, "/drinks", "Slot 3 is empty"),
+      APIError::ServerError(_, _)
+    ));
+  }
+
+  #[test]
+  fn forbidden_means_403() {
+    let error = |status: u16, message: &str| {
+      let status = StatusCode::from_u16(status).unwrap();
+      APIError::from_response(status, None, None, message.to_string())
+    };
+    assert!(matches!(
+      error(403, "Must be a drink admin"),
+      APIError::Forbidden(_)
+    ));
+    assert!(matches!(
+      error(400, "Invalid item admin"),
+      APIError::ServerError(_, _)
+    ));
   }
 }
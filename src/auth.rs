@@ -0,0 +1,604 @@
+//! Where tokens come from when there isn't a usable one cached. `API` tries
+//! a chain of [`AuthProvider`]s in order, see [`default_providers`]
+
+use crate::api::{APIConfig, APIError, PasswordFunction, PasswordResult};
+use crate::cache::{self, CachedToken};
+use crate::oidc::{self, LoginFlow};
+use curl::easy::{Auth, Easy};
+use isahc::HttpClient;
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::ptr;
+use std::sync::mpsc::channel;
+use url::Url;
+use users::get_current_username;
+
+/// What a provider may use to get a token
+pub struct AuthContext<'a> {
+  pub client: &'a HttpClient,
+  pub config: &'a APIConfig,
+  /// Asks the user for their password, see [`crate::API::set_password_prompt`]
+  pub password: &'a PasswordFunction,
+  /// Tells the user what to do to finish logging in, see [`crate::API::set_login_notice`]
+  pub notice: &'a dyn Fn(String),
+}
+
+/// One way of getting a token
+pub trait AuthProvider: Send + Sync {
+  /// `Ok(None)` means this provider has nothing to offer, so the next one
+  /// should be tried. An error ends the chain
+  fn token(&self, context: &AuthContext) -> Result<Option<CachedToken>, APIError>;
+
+  /// Whether the token is worth saving in the token cache for next time.
+  /// Tokens that are cheap to get again, or managed by someone else, aren't
+  fn cacheable(&self) -> bool {
+    true
+  }
+
+  /// Whether this provider holds a token we were handed right now. Such a
+  /// token beats whoever last logged in, so the token cache is left alone
+  fn is_given(&self) -> bool {
+    false
+  }
+}
+
+/// What `API::new` uses: any configured token, then however `config.login`
+/// says to log in.
+///
+/// For Kerberos that's whatever ticket GSSAPI finds in the default credentials
+/// cache, then clink's own ticket in `config.kerberos_cache`, then renewing
+/// that, and only then asking for a password and `kinit`ing into it
+pub fn default_providers(config: &APIConfig) -> Vec<Box<dyn AuthProvider>> {
+  let mut providers: Vec<Box<dyn AuthProvider>> = vec![];
+  if let Some(token) = &config.token {
+    providers.push(Box::new(StaticToken::new(token)));
+  }
+  if let Some(path) = &config.token_file {
+    providers.push(Box::new(TokenFile::new(path.clone())));
+  }
+  match config.login {
+    LoginFlow::Kerberos => {
+      providers.push(Box::new(Negotiate::new(None)));
+      let ccache = config.kerberos_cache.clone();
+      if ccache.is_some() {
+        providers.push(Box::new(Negotiate::new(ccache.clone())));
+      }
+      providers.push(Box::new(Renew::new(ccache.clone())));
+      providers.push(Box::new(PasswordPrompt::new(ccache)));
+    }
+    LoginFlow::Device => providers.push(Box::new(DeviceCode)),
+    LoginFlow::Pkce => providers.push(Box::new(Pkce)),
+  }
+  providers
+}
+
+/// The `KRB5CCNAME` value for the credentials cache file at `path`
+fn ccache_name(path: &Path) -> OsString {
+  let mut name = OsString::from("FILE:");
+  name.push(path);
+  name
+}
+
+/// Runs a Kerberos tool like `kinit` or `klist` against `ccache`, or the
+/// default credentials cache if `None`
+pub fn kerberos_command(program: &str, ccache: Option<&Path>) -> Command {
+  let mut command = Command::new(program);
+  if let Some(ccache) = ccache {
+    command.env("KRB5CCNAME", ccache_name(ccache));
+  }
+  command
+}
+
+extern "C" {
+  fn gss_krb5_ccache_name(
+    minor_status: *mut u32,
+    name: *const c_char,
+    out_name: *mut *const c_char,
+  ) -> u32;
+}
+
+/// Points GSSAPI on this thread alone at another credentials cache until
+/// dropped, so the rest of the process keeps using the default one
+struct CcacheScope;
+
+impl CcacheScope {
+  fn enter(ccache: &Path) -> Result<CcacheScope, APIError> {
+    let name = CString::new(ccache_name(ccache).into_vec())
+      .map_err(|_| APIError::ConfigError(format!("Bad credentials cache {}", ccache.display())))?;
+    let mut minor = 0;
+    // GSSAPI copies the name into thread-local storage
+    let major = unsafe { gss_krb5_ccache_name(&mut minor, name.as_ptr(), ptr::null_mut()) };
+    if major != 0 {
+      return Err(APIError::KerberosMissing(format!(
+        "Couldn't use credentials cache {}",
+        ccache.display()
+      )));
+    }
+    Ok(CcacheScope)
+  }
+}
+
+impl Drop for CcacheScope {
+  fn drop(&mut self) {
+    let mut minor = 0;
+    // No name means the default one again
+    unsafe { gss_krb5_ccache_name(&mut minor, ptr::null(), ptr::null_mut()) };
+  }
+}
+
+/// SPNEGO against SSO with the ticket in `ccache`, or whatever ticket is in
+/// the default credentials cache if `None`
+pub struct Negotiate {
+  ccache: Option<PathBuf>,
+}
+
+impl Negotiate {
+  pub fn new(ccache: Option<PathBuf>) -> Negotiate {
+    Negotiate { ccache }
+  }
+}
+
+impl AuthProvider for Negotiate {
+  fn token(&self, context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+    sso_redirect(context, self.ccache.as_deref())
+  }
+}
+
+/// Does the SSO round-trip with the Kerberos ticket in `ccache`. `None` means
+/// SSO wanted a login page instead, so we don't have a usable ticket
+fn sso_redirect(
+  context: &AuthContext,
+  ccache: Option<&Path>,
+) -> Result<Option<CachedToken>, APIError> {
+  let authorization = oidc::AuthorizationRequest::new(context.config, "drink://callback");
+  let redirect = match follow(context, &authorization.url, ccache)? {
+    Some(redirect) => redirect,
+    None => return Ok(None),
+  };
+  match redirect {
+    Redirect {
+      code: Some(_),
+      ref state,
+      ..
+    } if state.as_deref() != Some(authorization.state.as_str()) => Err(APIError::SsoFailure(
+      "The redirect was for a different login".to_string(),
+    )),
+    Redirect {
+      code: Some(code), ..
+    } => authorization
+      .exchange(context.client, context.config, &code)
+      .map(Some),
+    // Clients only allowed the implicit flow turn code requests away
+    Redirect {
+      error: Some(ref error),
+      ..
+    } if error == "unauthorized_client" || error == "unsupported_response_type" => {
+      implicit(context, ccache)
+    }
+    redirect => Err(redirect.failure("No code in the redirect")),
+  }
+}
+
+/// Logs in with the implicit flow instead, see [`oidc::ImplicitRequest`]
+fn implicit(context: &AuthContext, ccache: Option<&Path>) -> Result<Option<CachedToken>, APIError> {
+  let request = oidc::ImplicitRequest::new(context.config, "drink://callback");
+  let redirect = match follow(context, &request.url, ccache)? {
+    Some(redirect) => redirect,
+    None => return Ok(None),
+  };
+  match redirect {
+    Redirect {
+      access_token: Some(_),
+      ref state,
+      ..
+    } if state.as_deref() != Some(request.state.as_str()) => Err(APIError::SsoFailure(
+      "The redirect was for a different login".to_string(),
+    )),
+    Redirect {
+      access_token: Some(access_token),
+      expires_in,
+      ..
+    } => Ok(Some(oidc::cached_token(access_token, expires_in))),
+    redirect => Err(redirect.failure("No access token in the redirect")),
+  }
+}
+
+/// What SSO put in the URL it redirected us to
+#[derive(Default)]
+struct Redirect {
+  code: Option<String>,
+  state: Option<String>,
+  access_token: Option<String>,
+  expires_in: Option<u64>,
+  error: Option<String>,
+  error_description: Option<String>,
+}
+
+impl Redirect {
+  /// The error SSO sent, preferring the human-readable description, or
+  /// `otherwise` if it didn't send one
+  fn failure(self, otherwise: &str) -> APIError {
+    APIError::SsoFailure(
+      self
+        .error_description
+        .or(self.error)
+        .unwrap_or_else(|| otherwise.to_string()),
+    )
+  }
+}
+
+/// Sends `url` to SSO along with the Kerberos ticket in `ccache`. `None`
+/// means SSO wanted a login page instead of redirecting, so we don't have a
+/// usable ticket
+fn follow(
+  context: &AuthContext,
+  url: &str,
+  ccache: Option<&Path>,
+) -> Result<Option<Redirect>, APIError> {
+  let (status, location) = {
+    let _scope = ccache.map(CcacheScope::enter).transpose()?;
+    negotiate(context.config, url).map_err(|err| {
+      APIError::SsoFailure(format!(
+        "Couldn't reach {}: {}",
+        context.config.oidc_issuer, err
+      ))
+    })?
+  };
+  if status >= 500 {
+    return Err(APIError::SsoFailure(format!(
+      "{} replied {}",
+      context.config.oidc_issuer, status
+    )));
+  }
+  let location = match location {
+    Some(location) => location,
+    None => return Ok(None),
+  };
+  // Implicit flow answers come in the fragment, codes in the query
+  let url = Url::parse(&location.replace('#', "?")).map_err(|_| APIError::BadFormat)?;
+
+  let mut redirect = Redirect::default();
+  for (key, value) in url.query_pairs() {
+    let value = Some(value.to_string());
+    match key.as_ref() {
+      "code" => redirect.code = value,
+      "state" => redirect.state = value,
+      "access_token" => redirect.access_token = value,
+      "expires_in" => redirect.expires_in = value.and_then(|value| value.parse().ok()),
+      "error" => redirect.error = value,
+      "error_description" => redirect.error_description = value,
+      _ => {}
+    }
+  }
+  Ok(Some(redirect))
+}
+
+/// GETs `url` with SPNEGO, returning the status and any `Location`. This
+/// goes through curl on the calling thread rather than the API's client,
+/// whose requests are sent from a thread of its own, because GSSAPI only looks
+/// at the credentials cache a [`CcacheScope`] picks on the thread it's in
+fn negotiate(config: &APIConfig, url: &str) -> Result<(u32, Option<String>), curl::Error> {
+  let mut easy = Easy::new();
+  easy.url(url)?;
+  easy.useragent(concat!("clink/", env!("CARGO_PKG_VERSION")))?;
+  easy.timeout(config.timeout)?;
+  easy.connect_timeout(config.connect_timeout)?;
+  if let Some(proxy) = &config.proxy {
+    easy.proxy(proxy)?;
+  }
+  if let Some(ca_cert) = &config.ca_cert {
+    easy.cainfo(ca_cert)?;
+  }
+  easy.http_auth(Auth::new().gssnegotiate(true))?;
+  // curl only authenticates when there are credentials, even empty ones
+  easy.username("")?;
+  easy.password("")?;
+  let mut location = None;
+  {
+    let mut transfer = easy.transfer();
+    transfer.header_function(|header| {
+      let header = String::from_utf8_lossy(header);
+      match header.split_once(':') {
+        // A new response, e.g. after a 401 asking us to negotiate
+        _ if header.starts_with("HTTP/") => location = None,
+        Some((name, value)) if name.eq_ignore_ascii_case("Location") => {
+          location = Some(value.trim().to_string())
+        }
+        _ => {}
+      }
+      true
+    })?;
+    transfer.write_function(|data| Ok(data.len()))?;
+    transfer.perform()?;
+  }
+  Ok((easy.response_code()?, location))
+}
+
+/// Renews the ticket in `ccache` with `kinit -R`, then does what
+/// [`Negotiate`] does. Skipped if there's no renewable ticket
+pub struct Renew {
+  ccache: Option<PathBuf>,
+}
+
+impl Renew {
+  pub fn new(ccache: Option<PathBuf>) -> Renew {
+    Renew { ccache }
+  }
+}
+
+impl AuthProvider for Renew {
+  fn token(&self, context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+    let status = kerberos_command("kinit", self.ccache.as_deref())
+      .arg("-R")
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .status();
+    // Not renewable, past its renewal lifetime, or no kinit at all
+    match status {
+      Ok(status) if status.success() => sso_redirect(context, self.ccache.as_deref()),
+      _ => Ok(None),
+    }
+  }
+}
+
+/// Asks for a password, `kinit`s into `ccache` with it, then does what
+/// [`Negotiate`] does
+pub struct PasswordPrompt {
+  ccache: Option<PathBuf>,
+}
+
+impl PasswordPrompt {
+  pub fn new(ccache: Option<PathBuf>) -> PasswordPrompt {
+    PasswordPrompt { ccache }
+  }
+}
+
+impl AuthProvider for PasswordPrompt {
+  fn token(&self, context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+    if let Some(ccache) = &self.ccache {
+      // kinit makes the cache file, but not the directory it goes in
+      cache::create_parent(ccache).ok();
+    }
+    kinit(context, self.ccache.clone())?;
+    match sso_redirect(context, self.ccache.as_deref())? {
+      Some(token) => Ok(Some(token)),
+      None => Err(APIError::SsoFailure(
+        "Got a Kerberos ticket, but SSO still wouldn't take it".to_string(),
+      )),
+    }
+  }
+}
+
+fn kinit(context: &AuthContext, ccache: Option<PathBuf>) -> Result<(), APIError> {
+  // Get credentials
+  let username: String = context
+    .config
+    .username
+    .clone()
+    .or_else(|| get_current_username().and_then(|username| username.into_string().ok()))
+    .or_else(|| std::env::var("USER").ok())
+    .expect("Couldn't determine username");
+
+  let principal = format!("{}@{}", username, context.config.kerberos_realm);
+  let (tx_password, rx_password) = channel();
+  // Get password
+  (context.password)(
+    username.clone(),
+    Box::new(move |password| {
+      // Start kinit, ready to get password from pipe
+      let mut process = match kerberos_command("kinit", ccache.as_deref())
+        .arg(&principal)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+      {
+        Ok(process) => process,
+        Err(err) => {
+          let message = format!("Couldn't run kinit: {}", err);
+          tx_password
+            .send(Err(APIError::KerberosMissing(message.clone())))
+            .unwrap();
+          return Err(APIError::KerberosMissing(message));
+        }
+      };
+      process
+        .stdin
+        .as_ref()
+        .unwrap()
+        .write_all(password.as_bytes())
+        .unwrap();
+      let success = process.wait().unwrap().success();
+      if success {
+        tx_password.send(Ok(())).unwrap();
+      }
+      let mut output = "".to_string();
+      process.stderr.unwrap().read_to_string(&mut output).unwrap();
+      Ok(PasswordResult {
+        success,
+        message: output,
+      })
+    }),
+  );
+  match rx_password.recv() {
+    Ok(result) => result,
+    Err(_) => Err(APIError::LoginAborted),
+  }
+}
+
+/// The OAuth device authorization grant, see [`LoginFlow::Device`]
+pub struct DeviceCode;
+
+impl AuthProvider for DeviceCode {
+  fn token(&self, context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+    oidc::device(context.client, context.config, context.notice).map(Some)
+  }
+}
+
+/// The authorization code flow with PKCE, see [`LoginFlow::Pkce`]
+pub struct Pkce;
+
+impl AuthProvider for Pkce {
+  fn token(&self, context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+    oidc::pkce(context.client, context.config, context.notice).map(Some)
+  }
+}
+
+/// A token we were handed, e.g. by a bot's own login
+pub struct StaticToken {
+  token: String,
+}
+
+impl StaticToken {
+  /// `token` may or may not have a `Bearer ` prefix
+  pub fn new(token: &str) -> StaticToken {
+    let token = token.trim();
+    StaticToken {
+      token: token.strip_prefix("Bearer ").unwrap_or(token).to_string(),
+    }
+  }
+}
+
+impl AuthProvider for StaticToken {
+  fn token(&self, _context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+    Ok(Some(oidc::cached_token(self.token.clone(), None)))
+  }
+
+  fn cacheable(&self) -> bool {
+    false
+  }
+
+  fn is_given(&self) -> bool {
+    true
+  }
+}
+
+/// A token kept in a file by something else, read every time we need one so
+/// it can be rotated underneath us. A missing file is skipped
+pub struct TokenFile {
+  path: PathBuf,
+}
+
+impl TokenFile {
+  pub fn new(path: PathBuf) -> TokenFile {
+    TokenFile { path }
+  }
+}
+
+impl AuthProvider for TokenFile {
+  fn token(&self, context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+    match fs::read_to_string(&self.path) {
+      Ok(contents) if contents.trim().is_empty() => Ok(None),
+      Ok(contents) => StaticToken::new(&contents).token(context),
+      Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(APIError::ConfigError(format!(
+        "Couldn't read {}: {}",
+        self.path.display(),
+        err
+      ))),
+    }
+  }
+
+  fn cacheable(&self) -> bool {
+    false
+  }
+
+  fn is_given(&self) -> bool {
+    fs::read_to_string(&self.path).is_ok_and(|contents| !contents.trim().is_empty())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::api::API;
+
+  struct Nothing;
+
+  impl AuthProvider for Nothing {
+    fn token(&self, _context: &AuthContext) -> Result<Option<CachedToken>, APIError> {
+      Ok(None)
+    }
+  }
+
+  fn config() -> APIConfig {
+    APIConfig {
+      token_cache: None,
+      ..APIConfig::default()
+    }
+  }
+
+  #[test]
+  fn tries_providers_in_order() {
+    let api = API::with_providers(
+      config(),
+      vec![
+        Box::new(Nothing),
+        Box::new(StaticToken::new("Bearer first")),
+        Box::new(StaticToken::new("second")),
+      ],
+    )
+    .unwrap();
+    assert_eq!(api.get_token().unwrap(), "Bearer first");
+  }
+
+  #[test]
+  fn runs_out_of_providers() {
+    let api = API::with_providers(config(), vec![Box::new(Nothing)]).unwrap();
+    assert!(matches!(api.get_token(), Err(APIError::Unauthorized)));
+  }
+
+  #[test]
+  fn token_files_can_be_missing() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("token");
+    let api = API::with_providers(
+      config(),
+      vec![
+        Box::new(TokenFile::new(path.clone())),
+        Box::new(StaticToken::new("fallback")),
+      ],
+    )
+    .unwrap();
+    assert_eq!(api.get_token().unwrap(), "Bearer fallback");
+    fs::write(&path, "from-file\n").unwrap();
+    api.invalidate_token();
+    assert_eq!(api.get_token().unwrap(), "Bearer from-file");
+  }
+
+  #[test]
+  fn falls_back_to_clinks_own_cache() {
+    let private = APIConfig {
+      kerberos_cache: Some(PathBuf::from("/tmp/krb5cc")),
+      ..config()
+    };
+    // The default cache, then ours, renewing ours, and kinit into ours
+    assert_eq!(default_providers(&private).len(), 4);
+    let default = APIConfig {
+      kerberos_cache: None,
+      ..config()
+    };
+    assert_eq!(default_providers(&default).len(), 3);
+  }
+
+  #[test]
+  fn kerberos_tools_only_see_the_cache_theyre_given() {
+    let command = kerberos_command("klist", Some(Path::new("/tmp/krb5cc")));
+    assert_eq!(
+      command.get_envs().collect::<Vec<_>>(),
+      [("KRB5CCNAME".as_ref(), Some("FILE:/tmp/krb5cc".as_ref()))]
+    );
+    assert_eq!(kerberos_command("klist", None).get_envs().count(), 0);
+  }
+
+  #[test]
+  fn ccache_names_are_files() {
+    assert_eq!(ccache_name(Path::new("/tmp/krb5cc")), "FILE:/tmp/krb5cc");
+  }
+}
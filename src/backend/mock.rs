@@ -0,0 +1,395 @@
+use crate::api::{APIError, DrinkList, Item, ItemUpdate, Machine, Slot, SlotUpdate, User};
+use crate::backend::DrinkBackend;
+use crate::cache::Snapshot;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Which `DrinkBackend` call an injected failure applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+  Status,
+  Credits,
+  SetCredits,
+  Drop,
+  Items,
+  UpdateSlot,
+  /// Creating, updating or deleting an item
+  EditItems,
+  Token,
+  UserInfo,
+}
+
+/// A drink server that lives entirely in memory and behaves the same way
+/// every time, for tests
+pub struct MockBackend {
+  state: Mutex<MockState>,
+}
+
+pub struct MockState {
+  pub machines: Vec<Machine>,
+  /// The item catalog, which should include everything in `machines`
+  pub items: Vec<Item>,
+  pub balance: i64,
+  pub username: String,
+  /// Whether we're a drink admin
+  pub admin: bool,
+  /// Everyone else's balances
+  pub users: BTreeMap<String, i64>,
+  /// What the last full machine list looked like, like `API` keeps on disk
+  pub snapshot: Option<Snapshot>,
+  /// Every successful drop, oldest first
+  pub drops: Vec<(String, u8)>,
+  failures: Vec<(Operation, APIError)>,
+}
+
+impl MockBackend {
+  pub fn new(machines: Vec<Machine>, balance: i64) -> Self {
+    let mut items: Vec<Item> = machines
+      .iter()
+      .flat_map(|machine| &machine.slots)
+      .map(|slot| slot.item.clone())
+      .collect();
+    items.sort_by_key(|item| item.id);
+    items.dedup_by_key(|item| item.id);
+    MockBackend {
+      state: Mutex::new(MockState {
+        machines,
+        items,
+        balance,
+        username: "mock".to_string(),
+        admin: false,
+        users: BTreeMap::from([("someone".to_string(), 20)]),
+        snapshot: None,
+        drops: vec![],
+        failures: vec![],
+      }),
+    }
+  }
+
+  /// Two machines, one offline, with a mix of stocked, empty and disabled slots,
+  /// plus an item that isn't stocked anywhere
+  pub fn sample() -> Self {
+    let slot = |machine: u64, number: u8, id: u64, name: &str, price: u64, count: u64| Slot {
+      active: true,
+      count: Some(count),
+      empty: count == 0,
+      item: Item {
+        id,
+        name: name.to_string(),
+        price,
+      },
+      machine,
+      number,
+    };
+    let mut disabled = slot(1, 4, 4, "Root Beer", 50, 3);
+    disabled.active = false;
+    let backend = MockBackend::new(
+      vec![
+        Machine {
+          display_name: "Big Drink".to_string(),
+          id: 1,
+          is_online: true,
+          name: "bigdrink".to_string(),
+          slots: vec![
+            slot(1, 1, 1, "Coke", 50, 5),
+            slot(1, 2, 2, "Cherry Coke", 50, 1),
+            slot(1, 3, 3, "Water", 25, 0),
+            disabled,
+          ],
+        },
+        Machine {
+          display_name: "Little Drink".to_string(),
+          id: 2,
+          is_online: false,
+          name: "littledrink".to_string(),
+          slots: vec![slot(2, 1, 2, "Cherry Coke", 50, 8)],
+        },
+      ],
+      100,
+    );
+    // Something in the catalog that isn't in any slot
+    backend.with_state(|state| {
+      state.items.push(Item {
+        id: 5,
+        name: "Sprite".to_string(),
+        price: 40,
+      })
+    });
+    backend
+  }
+
+  /// Makes the next call to `operation` fail with `error`. Queued failures
+  /// are used up in the order they were added
+  pub fn fail_next(&self, operation: Operation, error: APIError) {
+    self.state.lock().unwrap().failures.push((operation, error));
+  }
+
+  /// Reads or tweaks the backend's state directly
+  pub fn with_state<R>(&self, f: impl FnOnce(&mut MockState) -> R) -> R {
+    f(&mut self.state.lock().unwrap())
+  }
+
+  pub fn balance(&self) -> i64 {
+    self.with_state(|state| state.balance)
+  }
+
+  fn check(&self, state: &mut MockState, operation: Operation) -> Result<(), APIError> {
+    match state.failures.iter().position(|(op, _)| *op == operation) {
+      Some(index) => Err(state.failures.remove(index).1),
+      None => Ok(()),
+    }
+  }
+}
+
+impl DrinkBackend for MockBackend {
+  fn get_status_for_machine(&self, machine: Option<&str>) -> Result<DrinkList, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::Status)?;
+    let machines: Vec<Machine> = state
+      .machines
+      .iter()
+      .filter(|candidate| machine.map(|name| candidate.name == name).unwrap_or(true))
+      .cloned()
+      .collect();
+    if let (Some(machine), true) = (machine, machines.is_empty()) {
+      return Err(APIError::NotFound(format!("No machine named {}", machine)));
+    }
+    let drinks = DrinkList {
+      machines,
+      message: "Successfully retrieved machine contents".to_string(),
+    };
+    if machine.is_none() {
+      state.snapshot = Some(Snapshot::new(drinks.clone()));
+    }
+    Ok(drinks)
+  }
+
+  fn get_credits(&self) -> Result<i64, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::Credits)?;
+    Ok(state.balance)
+  }
+
+  fn get_credits_for(&self, uid: &str) -> Result<i64, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::Credits)?;
+    if uid == state.username {
+      return Ok(state.balance);
+    }
+    if !state.admin {
+      return Err(APIError::Forbidden("Must be a drink admin".to_string()));
+    }
+    state
+      .users
+      .get(uid)
+      .copied()
+      .ok_or_else(|| APIError::NotFound(format!("No user named {}", uid)))
+  }
+
+  fn set_credits(&self, uid: &str, balance: i64) -> Result<(), APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::SetCredits)?;
+    if !state.admin {
+      return Err(APIError::Forbidden("Must be a drink admin".to_string()));
+    }
+    if uid == state.username {
+      state.balance = balance;
+      return Ok(());
+    }
+    match state.users.get_mut(uid) {
+      Some(credits) => *credits = balance,
+      None => return Err(APIError::NotFound(format!("No user named {}", uid))),
+    }
+    Ok(())
+  }
+
+  fn drop(&self, machine: String, slot: u8) -> Result<i64, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::Drop)?;
+    let balance = state.balance;
+    let found = state
+      .machines
+      .iter_mut()
+      .find(|candidate| candidate.name == machine)
+      .ok_or_else(|| APIError::NotFound(format!("No machine named {}", machine)))?;
+    if !found.is_online {
+      return Err(APIError::MachineOffline(format!("{} is offline", machine)));
+    }
+    let found = found
+      .slots
+      .iter_mut()
+      .find(|candidate| candidate.number == slot)
+      .ok_or_else(|| APIError::NotFound(format!("No slot {} in {}", slot, machine)))?;
+    if !found.active || found.empty {
+      return Err(APIError::SlotUnavailable(format!(
+        "Slot {} is empty or disabled",
+        slot
+      )));
+    }
+    let price = found.item.price as i64;
+    if balance < price {
+      return Err(APIError::InsufficientCredits(
+        "Not enough credits".to_string(),
+      ));
+    }
+    found.count = found.count.map(|count| count.saturating_sub(1));
+    found.empty = found.count == Some(0);
+    state.balance -= price;
+    state.drops.push((machine, slot));
+    Ok(state.balance)
+  }
+
+  fn list_items(&self) -> Result<Vec<Item>, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::Items)?;
+    Ok(state.items.clone())
+  }
+
+  fn update_slot(&self, machine: &str, slot: u8, update: &SlotUpdate) -> Result<(), APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::UpdateSlot)?;
+    if !state.admin {
+      return Err(APIError::Forbidden("Must be a drink admin".to_string()));
+    }
+    let item = match update.item_id {
+      Some(id) => Some(
+        state
+          .items
+          .iter()
+          .find(|item| item.id == id)
+          .cloned()
+          .ok_or_else(|| APIError::NotFound(format!("No item with id {}", id)))?,
+      ),
+      None => None,
+    };
+    let found = state
+      .machines
+      .iter_mut()
+      .find(|candidate| candidate.name == machine)
+      .and_then(|machine| {
+        machine
+          .slots
+          .iter_mut()
+          .find(|candidate| candidate.number == slot)
+      })
+      .ok_or_else(|| APIError::NotFound(format!("No slot {} in {}", slot, machine)))?;
+    if let Some(item) = item {
+      found.item = item;
+    }
+    if let Some(active) = update.active {
+      found.active = active;
+    }
+    if let Some(count) = update.count {
+      found.count = Some(count);
+      found.empty = count == 0;
+    }
+    Ok(())
+  }
+
+  fn create_item(&self, name: &str, price: u64) -> Result<Item, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::EditItems)?;
+    if !state.admin {
+      return Err(APIError::Forbidden("Must be a drink admin".to_string()));
+    }
+    let item = Item {
+      id: state.items.iter().map(|item| item.id).max().unwrap_or(0) + 1,
+      name: name.to_string(),
+      price,
+    };
+    state.items.push(item.clone());
+    Ok(item)
+  }
+
+  fn update_item(&self, update: &ItemUpdate) -> Result<(), APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::EditItems)?;
+    if !state.admin {
+      return Err(APIError::Forbidden("Must be a drink admin".to_string()));
+    }
+    let item = state
+      .items
+      .iter_mut()
+      .find(|item| item.id == update.id)
+      .ok_or_else(|| APIError::NotFound(format!("No item with id {}", update.id)))?;
+    if let Some(name) = &update.name {
+      item.name = name.clone();
+    }
+    if let Some(price) = update.price {
+      item.price = price;
+    }
+    // Slots hold copies of their item, keep them in step with the catalog
+    let item = item.clone();
+    for machine in &mut state.machines {
+      for slot in machine
+        .slots
+        .iter_mut()
+        .filter(|slot| slot.item.id == item.id)
+      {
+        slot.item = item.clone();
+      }
+    }
+    Ok(())
+  }
+
+  fn delete_item(&self, id: u64) -> Result<(), APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::EditItems)?;
+    if !state.admin {
+      return Err(APIError::Forbidden("Must be a drink admin".to_string()));
+    }
+    let before = state.items.len();
+    state.items.retain(|item| item.id != id);
+    match state.items.len() < before {
+      true => Ok(()),
+      false => Err(APIError::NotFound(format!("No item with id {}", id))),
+    }
+  }
+
+  fn cached_status(&self) -> Option<Snapshot> {
+    self.with_state(|state| state.snapshot.clone())
+  }
+
+  fn get_token(&self) -> Result<String, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::Token)?;
+    Ok("Bearer mock".to_string())
+  }
+
+  fn get_user_info(&self) -> Result<User, APIError> {
+    let mut state = self.state.lock().unwrap();
+    self.check(&mut state, Operation::UserInfo)?;
+    Ok(User {
+      preferred_username: state.username.clone(),
+      name: Some("Mock User".to_string()),
+      email: Some(format!("{}@csh.rit.edu", state.username)),
+      groups: vec!["member".to_string()],
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn drop_charges_and_decrements() {
+    let backend = MockBackend::sample();
+    assert_eq!(backend.drop("bigdrink".to_string(), 2).unwrap(), 50);
+    let slot = backend.with_state(|state| state.machines[0].slots[1].clone());
+    assert_eq!(slot.count, Some(0));
+    assert!(slot.empty);
+    assert!(matches!(
+      backend.drop("bigdrink".to_string(), 2),
+      Err(APIError::SlotUnavailable(_))
+    ));
+  }
+
+  #[test]
+  fn injected_failures_are_used_once() {
+    let backend = MockBackend::sample();
+    backend.fail_next(Operation::Credits, APIError::Unauthorized);
+    assert!(matches!(backend.get_credits(), Err(APIError::Unauthorized)));
+    assert_eq!(backend.get_credits().unwrap(), 100);
+  }
+}
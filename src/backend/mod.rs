@@ -0,0 +1,103 @@
+use crate::api::{APIError, DrinkList, Item, ItemUpdate, PasswordFunction, SlotUpdate, User, API};
+use crate::cache::Snapshot;
+use crate::oidc::LoginNoticeFunction;
+use std::path::PathBuf;
+
+pub mod mock;
+
+/// Everything the commands and the TUI need from a drink server.
+/// `API` talks to a real one, `mock::MockBackend` keeps it all in memory
+pub trait DrinkBackend: Send + Sync {
+  /// Lists `machine`, or every machine if `None`
+  fn get_status_for_machine(&self, machine: Option<&str>) -> Result<DrinkList, APIError>;
+  fn get_credits(&self) -> Result<i64, APIError>;
+  /// Looks up anyone's balance, only drink admins may look up other people
+  fn get_credits_for(&self, uid: &str) -> Result<i64, APIError>;
+  /// Overwrites `uid`'s balance, drink admins only
+  fn set_credits(&self, uid: &str, balance: i64) -> Result<(), APIError>;
+  /// Drops from `slot` of `machine`, returning the new balance
+  fn drop(&self, machine: String, slot: u8) -> Result<i64, APIError>;
+  /// Every item in the catalog, stocked or not
+  fn list_items(&self) -> Result<Vec<Item>, APIError>;
+  /// Changes what's in a slot, drink admins only
+  fn update_slot(&self, machine: &str, slot: u8, update: &SlotUpdate) -> Result<(), APIError>;
+  /// Adds an item to the catalog, drink admins only
+  fn create_item(&self, name: &str, price: u64) -> Result<Item, APIError>;
+  /// Renames or reprices an item, drink admins only
+  fn update_item(&self, update: &ItemUpdate) -> Result<(), APIError>;
+  /// Removes an item from the catalog, drink admins only
+  fn delete_item(&self, id: u64) -> Result<(), APIError>;
+  /// The last full machine list fetched, for when the server can't be reached
+  fn cached_status(&self) -> Option<Snapshot> {
+    None
+  }
+  /// Where successful drops are recorded, if anywhere
+  fn history_path(&self) -> Option<PathBuf> {
+    None
+  }
+  /// Where clink keeps its own Kerberos tickets, if anywhere
+  fn kerberos_cache(&self) -> Option<PathBuf> {
+    None
+  }
+  /// Full `Authorization` header value
+  fn get_token(&self) -> Result<String, APIError>;
+  fn get_user_info(&self) -> Result<User, APIError>;
+  /// Replaces how the backend asks for a password, for backends that ever do
+  fn set_password_prompt(&mut self, _prompt: Box<PasswordFunction>) {}
+  /// Replaces how the backend tells the user how to finish logging in
+  fn set_login_notice(&mut self, _notice: Box<LoginNoticeFunction>) {}
+}
+
+impl DrinkBackend for API {
+  fn get_status_for_machine(&self, machine: Option<&str>) -> Result<DrinkList, APIError> {
+    API::get_status_for_machine(self, machine)
+  }
+  fn get_credits(&self) -> Result<i64, APIError> {
+    API::get_credits(self)
+  }
+  fn get_credits_for(&self, uid: &str) -> Result<i64, APIError> {
+    API::get_credits_for(self, uid)
+  }
+  fn set_credits(&self, uid: &str, balance: i64) -> Result<(), APIError> {
+    API::set_credits(self, uid, balance)
+  }
+  fn drop(&self, machine: String, slot: u8) -> Result<i64, APIError> {
+    API::drop(self, machine, slot)
+  }
+  fn list_items(&self) -> Result<Vec<Item>, APIError> {
+    API::list_items(self)
+  }
+  fn update_slot(&self, machine: &str, slot: u8, update: &SlotUpdate) -> Result<(), APIError> {
+    API::update_slot(self, machine, slot, update)
+  }
+  fn create_item(&self, name: &str, price: u64) -> Result<Item, APIError> {
+    API::create_item(self, name, price)
+  }
+  fn update_item(&self, update: &ItemUpdate) -> Result<(), APIError> {
+    API::update_item(self, update)
+  }
+  fn delete_item(&self, id: u64) -> Result<(), APIError> {
+    API::delete_item(self, id)
+  }
+  fn cached_status(&self) -> Option<Snapshot> {
+    API::cached_status(self)
+  }
+  fn history_path(&self) -> Option<PathBuf> {
+    API::history_path(self)
+  }
+  fn kerberos_cache(&self) -> Option<PathBuf> {
+    API::kerberos_cache(self)
+  }
+  fn get_token(&self) -> Result<String, APIError> {
+    API::get_token(self)
+  }
+  fn get_user_info(&self) -> Result<User, APIError> {
+    API::get_user_info(self)
+  }
+  fn set_password_prompt(&mut self, prompt: Box<PasswordFunction>) {
+    API::set_password_prompt(self, prompt)
+  }
+  fn set_login_notice(&mut self, notice: Box<LoginNoticeFunction>) {
+    API::set_login_notice(self, notice)
+  }
+}
@@ -0,0 +1,135 @@
+use crate::api::DrinkList;
+use crate::output;
+use serde::{de, Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Tokens this close to expiring are treated as already expired, so they
+/// don't run out halfway through a request
+const EXPIRY_SKEW_SECS: u64 = 30;
+
+/// `$XDG_CACHE_HOME/clink`, where we keep things that are fine to lose
+pub fn cache_dir() -> Option<PathBuf> {
+  dirs::cache_dir().map(|dir| dir.join("clink"))
+}
+
+/// Each profile gets its own token, since they're for different servers
+pub fn token_path(profile: &str) -> Option<PathBuf> {
+  cache_dir().map(|dir| dir.join(format!("token-{}.json", profile)))
+}
+
+/// The last full machine list fetched for each profile
+pub fn snapshot_path(profile: &str) -> Option<PathBuf> {
+  cache_dir().map(|dir| dir.join(format!("drinks-{}.json", profile)))
+}
+
+/// Where kinit puts the tickets clink asks for, so they don't replace
+/// whatever's in the user's own credentials cache
+pub fn ccache_path(profile: &str) -> Option<PathBuf> {
+  cache_dir().map(|dir| dir.join(format!("krb5cc-{}", profile)))
+}
+
+pub fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_secs())
+    .unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedToken {
+  /// Full `Authorization` header value, including the `Bearer ` prefix
+  pub token: String,
+  /// Unix timestamp after which the token is no good, if we know it
+  pub expires_at: Option<u64>,
+  /// Trades for a new `token` without logging in again, if SSO gave us one
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub refresh_token: Option<String>,
+  /// Unix timestamp after which `refresh_token` is no good, if it ever is
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub refresh_expires_at: Option<u64>,
+}
+
+impl CachedToken {
+  pub fn is_expired(&self) -> bool {
+    match self.expires_at {
+      Some(expires_at) => now() + EXPIRY_SKEW_SECS >= expires_at,
+      None => false,
+    }
+  }
+
+  /// Whether it's worth trying `refresh_token` before logging in again
+  pub fn can_refresh(&self) -> bool {
+    match (&self.refresh_token, self.refresh_expires_at) {
+      (Some(_), Some(expires_at)) => now() + EXPIRY_SKEW_SECS < expires_at,
+      (Some(_), None) => true,
+      (None, _) => false,
+    }
+  }
+}
+
+/// A machine list and when we fetched it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+  pub fetched_at: u64,
+  pub drinks: DrinkList,
+}
+
+impl Snapshot {
+  pub fn new(drinks: DrinkList) -> Snapshot {
+    Snapshot {
+      fetched_at: now(),
+      drinks,
+    }
+  }
+
+  /// Seconds since the snapshot was taken
+  pub fn age(&self) -> u64 {
+    now().saturating_sub(self.fetched_at)
+  }
+
+  /// When the snapshot was taken, for telling people how stale it is
+  pub fn as_of(&self) -> String {
+    output::format_timestamp(self.fetched_at)
+  }
+}
+
+pub fn read<T: de::DeserializeOwned>(path: &Path) -> Option<T> {
+  let contents = fs::read(path).ok()?;
+  serde_json::from_slice(&contents).ok()
+}
+
+/// Writes `value` to `path` as JSON, readable only by the current user
+pub fn write<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+  create_parent(path)?;
+  let contents = serde_json::to_vec(value).map_err(io::Error::from)?;
+  // Write to a temporary file first so a concurrent clink never sees half a token
+  let temporary = path.with_extension("tmp");
+  let mut file = OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(&temporary)?;
+  file.write_all(&contents)?;
+  fs::rename(&temporary, path)
+}
+
+/// Makes the directory `path` goes in, readable only by the current user
+pub fn create_parent(path: &Path) -> io::Result<()> {
+  match path.parent() {
+    Some(parent) => fs::DirBuilder::new()
+      .recursive(true)
+      .mode(0o700)
+      .create(parent),
+    None => Ok(()),
+  }
+}
+
+pub fn remove(path: &Path) {
+  // Missing is just as good as removed
+  fs::remove_file(path).ok();
+}
@@ -0,0 +1,220 @@
+use crate::api::{APIError, Item, ItemUpdate};
+use crate::backend::DrinkBackend;
+use crate::commands::admin::find_item;
+use crate::output::OutputFormat;
+use crate::prompt;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct ItemChangeOutput {
+  before: Option<Item>,
+  after: Option<Item>,
+}
+
+fn print_items(items: &[Item], format: OutputFormat) {
+  match format {
+    OutputFormat::Plain => {
+      for item in items {
+        println!("{:>4}  {} ({} Credits)", item.id, item.name, item.price);
+      }
+    }
+    OutputFormat::Json => OutputFormat::print_json(&items),
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &["id", "name", "price"],
+      &items
+        .iter()
+        .map(|item| {
+          vec![
+            item.id.to_string(),
+            item.name.clone(),
+            item.price.to_string(),
+          ]
+        })
+        .collect::<Vec<_>>(),
+    ),
+  }
+}
+
+fn print_change(message: String, before: Option<Item>, after: Option<Item>, format: OutputFormat) {
+  match format {
+    OutputFormat::Plain => println!("{}", message),
+    OutputFormat::Json => OutputFormat::print_json(&ItemChangeOutput { before, after }),
+    OutputFormat::Csv | OutputFormat::Tsv => {
+      let fields = |item: &Option<Item>| match item {
+        Some(item) => vec![
+          item.id.to_string(),
+          item.name.clone(),
+          item.price.to_string(),
+        ],
+        None => vec![String::new(); 3],
+      };
+      format.print_table(
+        &["change", "id", "name", "price"],
+        &[
+          [vec!["before".to_string()], fields(&before)].concat(),
+          [vec!["after".to_string()], fields(&after)].concat(),
+        ],
+      )
+    }
+  }
+}
+
+pub fn list(api: &dyn DrinkBackend, format: OutputFormat) -> Result<(), APIError> {
+  let mut items = api.list_items()?;
+  items.sort_by_key(|item| item.id);
+  print_items(&items, format);
+  Ok(())
+}
+
+pub fn add(
+  api: &dyn DrinkBackend,
+  name: String,
+  price: u64,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let item = api.create_item(&name, price)?;
+  print_change(
+    format!(
+      "Added {} ({}) at {} credits",
+      item.name, item.id, item.price
+    ),
+    None,
+    Some(item),
+    format,
+  );
+  Ok(())
+}
+
+/// Renames and/or reprices an item. Price changes are confirmed first when
+/// `confirm` is set, and refused if there's no one at the terminal to confirm
+pub fn edit(
+  api: &dyn DrinkBackend,
+  query: String,
+  name: Option<String>,
+  price: Option<u64>,
+  confirm: bool,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let items = api.list_items()?;
+  let before = find_item(&items, &query)?.clone();
+  let price = price.filter(|price| *price != before.price);
+  if let (Some(price), true) = (price, confirm) {
+    prompt::require_confirmation(&format!(
+      "Change the price of {} from {} to {} credits?",
+      before.name, before.price, price
+    ))?;
+  }
+  api.update_item(&ItemUpdate {
+    id: before.id,
+    name: name.clone(),
+    price,
+  })?;
+  let after = Item {
+    id: before.id,
+    name: name.unwrap_or_else(|| before.name.clone()),
+    price: price.unwrap_or(before.price),
+  };
+  let mut changes = vec![];
+  if after.name != before.name {
+    changes.push(format!("name {} -> {}", before.name, after.name));
+  }
+  if after.price != before.price {
+    changes.push(format!("price {} -> {} credits", before.price, after.price));
+  }
+  let message = match changes.is_empty() {
+    true => format!("{} ({}): no changes", before.name, before.id),
+    false => format!("{} ({}): {}", before.name, before.id, changes.join(", ")),
+  };
+  print_change(message, Some(before), Some(after), format);
+  Ok(())
+}
+
+/// Deletes an item, unless a slot somewhere still has it. Confirmed first
+/// like a price change in [`edit`]
+pub fn rm(
+  api: &dyn DrinkBackend,
+  query: String,
+  confirm: bool,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let items = api.list_items()?;
+  let item = find_item(&items, &query)?.clone();
+  let drinks = api.get_status_for_machine(None)?;
+  let stocked: Vec<String> = drinks
+    .machines
+    .iter()
+    .flat_map(|machine| {
+      machine
+        .slots
+        .iter()
+        .filter(|slot| slot.item.id == item.id)
+        .map(move |slot| format!("{} slot {}", machine.name, slot.number))
+    })
+    .collect();
+  if !stocked.is_empty() {
+    return Err(APIError::InUse(format!(
+      "{} ({}) is still in {}",
+      item.name,
+      item.id,
+      stocked.join(", ")
+    )));
+  }
+  if confirm {
+    prompt::require_confirmation(&format!("Delete {} ({})?", item.name, item.id))?;
+  }
+  api.delete_item(item.id)?;
+  print_change(
+    format!("Deleted {} ({})", item.name, item.id),
+    Some(item),
+    None,
+    format,
+  );
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::mock::MockBackend;
+
+  #[test]
+  fn wont_delete_stocked_items() {
+    let backend = MockBackend::sample();
+    backend.with_state(|state| state.admin = true);
+    let result = rm(
+      &backend,
+      "cherry coke".to_string(),
+      false,
+      OutputFormat::Plain,
+    );
+    match result {
+      Err(APIError::InUse(message)) => {
+        assert!(message.contains("bigdrink slot 2, littledrink slot 1"))
+      }
+      _ => panic!("expected InUse"),
+    }
+    rm(&backend, "sprite".to_string(), false, OutputFormat::Plain).unwrap();
+    assert!(backend
+      .list_items()
+      .unwrap()
+      .iter()
+      .all(|item| item.name != "Sprite"));
+  }
+
+  #[test]
+  fn repricing_updates_slots() {
+    let backend = MockBackend::sample();
+    backend.with_state(|state| state.admin = true);
+    edit(
+      &backend,
+      "1".to_string(),
+      None,
+      Some(60),
+      false,
+      OutputFormat::Plain,
+    )
+    .unwrap();
+    let coke = backend.with_state(|state| state.machines[0].slots[0].item.clone());
+    assert_eq!(coke.price, 60);
+  }
+}
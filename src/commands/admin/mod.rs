@@ -0,0 +1,46 @@
+//! Commands for drink admins. The server decides who's an admin, everyone
+//! else gets `APIError::Forbidden`
+
+use crate::api::{APIError, Item};
+use crate::commands::drop::item_matches;
+
+pub mod credits;
+pub mod item;
+pub mod slot;
+
+/// Finds an item by id if `query` is a number, otherwise by name. Names
+/// can be fuzzy as long as only one item matches
+pub fn find_item<'a>(items: &'a [Item], query: &str) -> Result<&'a Item, APIError> {
+  if let Ok(id) = query.parse::<u64>() {
+    return items
+      .iter()
+      .find(|item| item.id == id)
+      .ok_or_else(|| APIError::NotFound(format!("There's no item with id {}", id)));
+  }
+  if let Some(item) = items
+    .iter()
+    .find(|item| item.name.eq_ignore_ascii_case(query))
+  {
+    return Ok(item);
+  }
+  let matches: Vec<&Item> = items
+    .iter()
+    .filter(|item| item_matches(query, &item.name))
+    .collect();
+  match matches.as_slice() {
+    [item] => Ok(item),
+    [] => Err(APIError::NotFound(format!(
+      "There's no item like \"{}\" (`clink admin item list` shows them all)",
+      query
+    ))),
+    _ => Err(APIError::NotFound(format!(
+      "\"{}\" could be any of {}, use the id to pick one",
+      query,
+      matches
+        .iter()
+        .map(|item| format!("{} ({})", item.name, item.id))
+        .collect::<Vec<_>>()
+        .join(", ")
+    ))),
+  }
+}
@@ -0,0 +1,316 @@
+use crate::api::{APIError, Item, Machine, Slot, SlotUpdate};
+use crate::backend::DrinkBackend;
+use crate::commands::admin::find_item;
+use crate::output::OutputFormat;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// An item given by id or by name
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ItemRef {
+  Id(u64),
+  Name(String),
+}
+
+impl fmt::Display for ItemRef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ItemRef::Id(id) => write!(f, "{}", id),
+      ItemRef::Name(name) => write!(f, "{}", name),
+    }
+  }
+}
+
+/// One slot's worth of changes, from the command line or a batch file
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SlotChange {
+  pub machine: String,
+  pub slot: u8,
+  pub item: Option<ItemRef>,
+  pub active: Option<bool>,
+  pub count: Option<u64>,
+}
+
+/// A `--from-file` batch, one `[[slot]]` table per slot
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Batch {
+  #[serde(rename = "slot", default)]
+  slots: Vec<SlotChange>,
+}
+
+#[derive(Serialize)]
+struct SlotDiff {
+  machine: String,
+  slot: u8,
+  before: Slot,
+  after: Option<Slot>,
+}
+
+/// Reads a batch of changes from a TOML file like
+///
+/// ```toml
+/// [[slot]]
+/// machine = "bigdrink"
+/// slot = 1
+/// item = "Coke" # or an item id
+/// count = 12
+/// ```
+pub fn load_batch(path: &Path) -> Result<Vec<SlotChange>, APIError> {
+  let contents = fs::read_to_string(path)
+    .map_err(|err| APIError::ConfigError(format!("Couldn't read {}: {}", path.display(), err)))?;
+  let batch: Batch = toml::from_str(&contents)
+    .map_err(|err| APIError::ConfigError(format!("Couldn't parse {}: {}", path.display(), err)))?;
+  Ok(batch.slots)
+}
+
+/// Turns a change into what the API wants, and finds the slot it applies to
+fn prepare(
+  change: &SlotChange,
+  machines: &[Machine],
+  items: &[Item],
+) -> Result<(SlotUpdate, Slot), APIError> {
+  let slot = machines
+    .iter()
+    .find(|machine| machine.name == change.machine)
+    .ok_or_else(|| APIError::NotFound(format!("There's no machine named {}", change.machine)))?
+    .slots
+    .iter()
+    .find(|slot| slot.number == change.slot)
+    .cloned()
+    .ok_or_else(|| {
+      APIError::NotFound(format!(
+        "{} doesn't have a slot {}",
+        change.machine, change.slot
+      ))
+    })?;
+  let item_id = match &change.item {
+    Some(item) => Some(find_item(items, &item.to_string())?.id),
+    None => None,
+  };
+  let update = SlotUpdate {
+    item_id,
+    active: change.active,
+    count: change.count,
+  };
+  if update == SlotUpdate::default() {
+    return Err(APIError::ConfigError(format!(
+      "Nothing to change for {} slot {}, give at least one of item, active or count",
+      change.machine, change.slot
+    )));
+  }
+  Ok((update, slot))
+}
+
+/// Applies `changes` in order, then prints each slot before and after.
+/// Everything is checked before anything is changed, but if the server
+/// rejects a change partway through, the earlier ones stay applied
+pub fn apply(
+  api: &dyn DrinkBackend,
+  changes: Vec<SlotChange>,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let machine = match changes.first() {
+    Some(first) if changes.iter().all(|change| change.machine == first.machine) => {
+      Some(first.machine.as_str())
+    }
+    _ => None,
+  };
+  let machines = api.get_status_for_machine(machine)?.machines;
+  let items = match changes.iter().any(|change| change.item.is_some()) {
+    true => api.list_items()?,
+    false => vec![],
+  };
+  let prepared = changes
+    .iter()
+    .map(|change| prepare(change, &machines, &items))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let mut applied = vec![];
+  let mut failure = None;
+  for (change, (update, before)) in changes.iter().zip(prepared) {
+    match api.update_slot(&change.machine, change.slot, &update) {
+      Ok(()) => applied.push((change, before)),
+      Err(err) => {
+        failure = Some(err);
+        break;
+      }
+    }
+  }
+
+  // Show what the server ended up with, rather than what we asked for
+  let machines = match applied.is_empty() {
+    true => vec![],
+    false => api.get_status_for_machine(machine)?.machines,
+  };
+  let diffs: Vec<SlotDiff> = applied
+    .into_iter()
+    .map(|(change, before)| SlotDiff {
+      machine: change.machine.clone(),
+      slot: change.slot,
+      after: machines
+        .iter()
+        .filter(|machine| machine.name == change.machine)
+        .flat_map(|machine| &machine.slots)
+        .find(|slot| slot.number == change.slot)
+        .cloned(),
+      before,
+    })
+    .collect();
+  print_diffs(&diffs, format);
+  match failure {
+    Some(err) if !diffs.is_empty() => {
+      eprintln!(
+        "Only the first {} of {} changes were made",
+        diffs.len(),
+        changes.len()
+      );
+      Err(err)
+    }
+    Some(err) => Err(err),
+    None => Ok(()),
+  }
+}
+
+/// (field, before, after) for every field that changed
+fn changed_fields(diff: &SlotDiff) -> Vec<(&'static str, String, String)> {
+  let after = match &diff.after {
+    Some(after) => after,
+    None => return vec![("slot", "present".to_string(), "missing".to_string())],
+  };
+  let describe = |item: &Item| format!("{} ({})", item.name, item.id);
+  let count = |count: Option<u64>| {
+    count
+      .map(|count| count.to_string())
+      .unwrap_or_else(|| "unknown".to_string())
+  };
+  let mut fields = vec![];
+  if diff.before.item.id != after.item.id {
+    fields.push(("item", describe(&diff.before.item), describe(&after.item)));
+  }
+  if diff.before.active != after.active {
+    fields.push((
+      "active",
+      diff.before.active.to_string(),
+      after.active.to_string(),
+    ));
+  }
+  if diff.before.count != after.count {
+    fields.push(("count", count(diff.before.count), count(after.count)));
+  }
+  fields
+}
+
+fn print_diffs(diffs: &[SlotDiff], format: OutputFormat) {
+  match format {
+    OutputFormat::Plain => {
+      for diff in diffs {
+        let fields = changed_fields(diff);
+        if fields.is_empty() {
+          println!("{} slot {}: no changes", diff.machine, diff.slot);
+          continue;
+        }
+        println!("{} slot {}:", diff.machine, diff.slot);
+        for (field, before, after) in fields {
+          println!("  {}: {} -> {}", field, before, after);
+        }
+      }
+    }
+    OutputFormat::Json => OutputFormat::print_json(&diffs),
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &["machine", "slot", "field", "before", "after"],
+      &diffs
+        .iter()
+        .flat_map(|diff| {
+          changed_fields(diff)
+            .into_iter()
+            .map(|(field, before, after)| {
+              vec![
+                diff.machine.clone(),
+                diff.slot.to_string(),
+                field.to_string(),
+                before,
+                after,
+              ]
+            })
+        })
+        .collect::<Vec<_>>(),
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::mock::{MockBackend, Operation};
+
+  fn change(slot: u8, item: Option<&str>) -> SlotChange {
+    SlotChange {
+      machine: "bigdrink".to_string(),
+      slot,
+      item: item.map(|item| ItemRef::Name(item.to_string())),
+      active: None,
+      count: Some(10),
+    }
+  }
+
+  #[test]
+  fn checks_everything_before_changing_anything() {
+    let backend = MockBackend::sample();
+    backend.with_state(|state| state.admin = true);
+    let result = apply(
+      &backend,
+      vec![change(1, Some("sprite")), change(2, Some("fanta"))],
+      OutputFormat::Plain,
+    );
+    assert!(matches!(result, Err(APIError::NotFound(_))));
+    let slot = backend.with_state(|state| state.machines[0].slots[0].clone());
+    assert_eq!(slot.item.name, "Coke");
+    assert_eq!(slot.count, Some(5));
+  }
+
+  #[test]
+  fn stops_at_the_first_rejected_change() {
+    let backend = MockBackend::sample();
+    backend.with_state(|state| state.admin = true);
+    backend.fail_next(
+      Operation::UpdateSlot,
+      APIError::ServerError(None, "Nope".to_string()),
+    );
+    let result = apply(
+      &backend,
+      vec![change(1, Some("sprite")), change(3, None)],
+      OutputFormat::Plain,
+    );
+    assert!(matches!(result, Err(APIError::ServerError(_, _))));
+    let water = backend.with_state(|state| state.machines[0].slots[2].clone());
+    assert_eq!(water.count, Some(0));
+  }
+
+  #[test]
+  fn parses_batches() {
+    let batch: Batch = toml::from_str(
+      r#"
+      [[slot]]
+      machine = "bigdrink"
+      slot = 1
+      item = 5
+      active = false
+
+      [[slot]]
+      machine = "bigdrink"
+      slot = 2
+      item = "Sprite"
+      count = 10
+      "#,
+    )
+    .unwrap();
+    assert_eq!(batch.slots[0].item, Some(ItemRef::Id(5)));
+    assert_eq!(batch.slots[0].active, Some(false));
+    assert_eq!(batch.slots[1], change(2, Some("Sprite")));
+  }
+}
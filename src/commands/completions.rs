@@ -0,0 +1,252 @@
+use crate::api::{APIError, DrinkList};
+use crate::cache::{self, Snapshot};
+use crate::config::Config;
+use clap::{Arg, Command};
+use clap_complete::Shell;
+use std::io;
+
+/// Completions only trust a machine list this many seconds old, past that
+/// the shell falls back to completing nothing rather than guessing
+const SNAPSHOT_MAX_AGE_SECS: u64 = 60 * 60;
+
+/// Hands the word being completed to `clink complete-word`, and falls back to
+/// the static completions when that exits non-zero
+const BASH_DYNAMIC: &str = r#"
+_clink_dynamic() {
+    local output candidate
+    if output="$(command clink complete-word --index "$COMP_CWORD" -- "${COMP_WORDS[@]}" 2>/dev/null)"; then
+        local IFS=$'\n'
+        COMPREPLY=()
+        for candidate in $(compgen -W "$(printf '%s\n' "$output" | cut -f1)" -- "${COMP_WORDS[COMP_CWORD]}"); do
+            COMPREPLY+=("$(printf '%q' "$candidate")")
+        done
+        return 0
+    fi
+    _clink "$@"
+}
+
+complete -F _clink_dynamic -o bashdefault -o default clink
+"#;
+
+const ZSH_DYNAMIC: &str = r#"
+_clink_dynamic() {
+    local output line
+    local -a described
+    if output="$(command clink complete-word --index $((CURRENT - 1)) -- "${words[@]}" 2>/dev/null)"; then
+        for line in ${(f)output}; do
+            described+=("${${line%%$'\t'*}//:/\\:}:${line#*$'\t'}")
+        done
+        _describe 'value' described
+        return
+    fi
+    _clink "$@"
+}
+
+"#;
+
+/// What clap_complete ends its zsh script with, which we point at `_clink_dynamic`
+const ZSH_DISPATCH: &str = r#"if [ "$funcstack[1]" = "_clink" ]; then"#;
+
+const FISH_DYNAMIC: &str = r#"
+function __clink_dynamic
+    set -l words (commandline -opc) (commandline -ct)
+    command clink complete-word --index (math (count $words) - 1) -- $words 2>/dev/null
+end
+
+complete -c clink -f -n '__clink_dynamic >/dev/null' -a '(__clink_dynamic)'
+"#;
+
+/// Prints a completion script for `shell`. Bash, zsh and fish also complete
+/// machines, slots, items and profiles via `clink complete-word`
+pub fn completions(shell: Shell, command: &mut Command) -> Result<(), APIError> {
+  let mut script = vec![];
+  clap_complete::generate(shell, command, "clink", &mut script);
+  // panic rationale: clap_complete only writes valid UTF-8
+  let script = String::from_utf8(script).unwrap();
+  let script = match shell {
+    Shell::Bash => script + BASH_DYNAMIC,
+    Shell::Fish => script + FISH_DYNAMIC,
+    Shell::Zsh => match script.find(ZSH_DISPATCH) {
+      Some(dispatch) => {
+        let (head, tail) = script.split_at(dispatch);
+        format!(
+          "{}{}{}",
+          head,
+          ZSH_DYNAMIC,
+          tail
+            .replace("_clink \"$@\"", "_clink_dynamic \"$@\"")
+            .replace("compdef _clink clink", "compdef _clink_dynamic clink")
+        )
+      }
+      None => format!("{}{}compdef _clink_dynamic clink\n", script, ZSH_DYNAMIC),
+    },
+    _ => script,
+  };
+  io::Write::write_all(&mut io::stdout(), script.as_bytes()).ok();
+  Ok(())
+}
+
+/// What the word being completed is
+#[derive(Debug, PartialEq, Eq)]
+pub enum Target {
+  Machines,
+  /// Slots of the named machine
+  Slots(String),
+  Items,
+  Profiles,
+}
+
+/// The flag `word` refers to, looking at the subcommand's flags and then the top-level ones
+fn find_flag<'a>(
+  command: &'a Command,
+  subcommand: Option<&'a Command>,
+  word: &str,
+) -> Option<&'a Arg> {
+  let matches = |arg: &&Arg| match word.strip_prefix("--") {
+    Some(long) => arg.get_long() == Some(long),
+    None => word.len() == 2 && arg.get_short() == word.chars().nth(1),
+  };
+  subcommand
+    .and_then(|subcommand| subcommand.get_arguments().find(matches))
+    .or_else(|| command.get_arguments().find(matches))
+}
+
+/// Works out what `words[index]` should be, and which profile was picked on the
+/// command line if any. `words[0]` is `clink` itself. `None` means there's
+/// nothing we can complete better than clap_complete already does
+pub fn target(
+  command: &Command,
+  words: &[String],
+  index: usize,
+) -> (Option<Target>, Option<String>) {
+  let mut subcommand: Option<&Command> = None;
+  let mut positionals: Vec<&str> = vec![];
+  let mut profile = None;
+  let mut i = 1;
+  while i < index.min(words.len()) {
+    let word = words[i].as_str();
+    if word.starts_with('-') && word != "-" && word != "--" {
+      let (flag, value) = match word.split_once('=') {
+        Some((flag, value)) => (flag, Some(value)),
+        None => (word, None),
+      };
+      let arg = find_flag(command, subcommand, flag);
+      let takes_value = arg
+        .map(|arg| arg.get_action().takes_values())
+        .unwrap_or(false);
+      let id = arg.map(|arg| arg.get_id().as_str());
+      match value {
+        Some(value) if id == Some("profile") => profile = Some(value.to_string()),
+        None if takes_value && i + 1 == index => {
+          let target = match id {
+            Some("machine") => Some(Target::Machines),
+            Some("item") => Some(Target::Items),
+            Some("profile") => Some(Target::Profiles),
+            _ => None,
+          };
+          return (target, profile);
+        }
+        None if takes_value => {
+          if id == Some("profile") {
+            profile = words.get(i + 1).cloned().or(profile);
+          }
+          i += 1;
+        }
+        _ => {}
+      }
+    } else if subcommand.is_none() {
+      subcommand = command.find_subcommand(word);
+    } else {
+      positionals.push(word);
+    }
+    i += 1;
+  }
+  if words.get(index).map(|word| word.starts_with('-')) == Some(true) {
+    return (None, profile);
+  }
+  let target = match (subcommand.map(Command::get_name), positionals.as_slice()) {
+    (Some("drop"), []) | (Some("list"), []) | (Some("watch"), []) => Some(Target::Machines),
+    (Some("drop"), [machine]) => Some(Target::Slots(machine.to_string())),
+    (Some("profile"), ["show"]) => Some(Target::Profiles),
+    _ => None,
+  };
+  (target, profile)
+}
+
+/// `value\tdescription` lines for `target`
+pub fn candidates(target: &Target, drinks: &DrinkList, file: &Config) -> Vec<String> {
+  match target {
+    Target::Machines => drinks
+      .machines
+      .iter()
+      .map(|machine| match machine.is_online {
+        true => format!("{}\t{}", machine.name, machine.display_name),
+        false => format!("{}\t{} (offline)", machine.name, machine.display_name),
+      })
+      .collect(),
+    Target::Slots(name) => drinks
+      .machines
+      .iter()
+      .filter(|machine| &machine.name == name)
+      .flat_map(|machine| &machine.slots)
+      .map(|slot| {
+        let state = match (slot.active, slot.empty || slot.count == Some(0)) {
+          (false, _) => " (disabled)",
+          (true, true) => " (empty)",
+          (true, false) => "",
+        };
+        format!(
+          "{}\t{}, {} credits{}",
+          slot.number, slot.item.name, slot.item.price, state
+        )
+      })
+      .collect(),
+    Target::Items => {
+      let mut items: Vec<String> = drinks
+        .machines
+        .iter()
+        .flat_map(|machine| &machine.slots)
+        .filter(|slot| slot.active)
+        .map(|slot| format!("{}\t{} credits", slot.item.name, slot.item.price))
+        .collect();
+      items.sort();
+      items.dedup();
+      items
+    }
+    Target::Profiles => file.profile_names(),
+  }
+}
+
+/// Prints candidates for `words[index]` from the cached machine list, never
+/// touching the network. Errors when the shell should use its static completions
+pub fn complete(
+  command: &Command,
+  file: &Config,
+  profile: &str,
+  index: usize,
+  words: &[String],
+) -> Result<(), APIError> {
+  let (target, picked) = target(command, words, index);
+  let target =
+    target.ok_or_else(|| APIError::NotFound("Nothing to complete dynamically".to_string()))?;
+  let profile = match picked {
+    Some(picked) => file.resolve(&picked)?,
+    None => file.resolve(profile)?,
+  };
+  let drinks = match target {
+    Target::Profiles => None,
+    _ => cache::snapshot_path(profile.profile_name())
+      .as_deref()
+      .and_then(cache::read::<Snapshot>)
+      .filter(|snapshot| snapshot.age() <= SNAPSHOT_MAX_AGE_SECS)
+      .map(|snapshot| snapshot.drinks),
+  };
+  let empty = DrinkList {
+    machines: vec![],
+    message: String::new(),
+  };
+  for candidate in candidates(&target, drinks.as_ref().unwrap_or(&empty), file) {
+    println!("{}", candidate);
+  }
+  Ok(())
+}
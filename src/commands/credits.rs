@@ -1,8 +1,22 @@
-use crate::api::{APIError, API};
+use crate::api::APIError;
+use crate::backend::DrinkBackend;
+use crate::output::OutputFormat;
+use serde::Serialize;
 
-pub fn credits(api: &mut API) -> Result<(), APIError> {
+#[derive(Serialize)]
+struct CreditsOutput {
+  credits: i64,
+}
+
+pub fn credits(api: &dyn DrinkBackend, format: OutputFormat) -> Result<(), APIError> {
   let credits = api.get_credits()?;
-  println!("{} credits", credits);
+  match format {
+    OutputFormat::Plain => println!("{} credits", credits),
+    OutputFormat::Json => OutputFormat::print_json(&CreditsOutput { credits }),
+    OutputFormat::Csv | OutputFormat::Tsv => {
+      format.print_table(&["credits"], &[vec![credits.to_string()]])
+    }
+  }
 
   Ok(())
 }
@@ -1,7 +1,425 @@
-use crate::api::{APIError, API};
+use crate::api::{APIError, Machine, Slot};
+use crate::backend::DrinkBackend;
+use crate::history::{self, Entry};
+use crate::output::OutputFormat;
+use crate::prompt;
+use serde::Serialize;
+use std::io::{self, BufRead, Write};
 
-pub fn drop(api: &mut API, machine: String, slot: u8) -> Result<(), APIError> {
-  let credits = api.drop(machine, slot)?;
-  println!("Item dropped! Your new balance is {}", credits);
+#[derive(Serialize)]
+struct DropOutput {
+  machine: String,
+  slot: u8,
+  credits: i64,
+}
+
+#[derive(Serialize)]
+struct DryRunOutput {
+  machine: String,
+  slot: u8,
+  item: Option<String>,
+  price: Option<u64>,
+  credits: Option<i64>,
+  projected_credits: Option<i64>,
+}
+
+pub struct DropOptions {
+  /// Skip the pre-flight checks, for when the server's idea of the machine is wrong
+  pub force: bool,
+  /// Show what would be dropped without dropping it
+  pub dry_run: bool,
+  /// Whether to ask before dropping, only honored when stdin is a terminal
+  pub confirm: bool,
+}
+
+pub fn drop(
+  api: &dyn DrinkBackend,
+  machine: String,
+  slot: u8,
+  options: &DropOptions,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let confirm = options.confirm && !options.dry_run && prompt::interactive();
+  let preflight = match options.force {
+    false => Some(preflight(api, &machine, slot)?),
+    // Still try to say what's being bought, but don't let a stale view stop us
+    true if options.dry_run || confirm => resolve(api, &machine, slot).ok(),
+    true => None,
+  };
+
+  if options.dry_run {
+    print_dry_run(machine, slot, preflight, format);
+    return Ok(());
+  }
+  if confirm {
+    prompt::confirm(&match &preflight {
+      Some(preflight) => format!(
+        "Drop {} ({} Credits) from {} slot {}? You'll have {} credits left",
+        preflight.slot.item.name,
+        preflight.slot.item.price,
+        preflight.machine.display_name,
+        slot,
+        preflight.projected_credits()
+      ),
+      None => format!("Drop whatever is in {} slot {}?", machine, slot),
+    })?;
+  }
+
+  let credits = api.drop(machine.clone(), slot)?;
+  history::record(
+    api.history_path().as_deref(),
+    &Entry::new(
+      machine.clone(),
+      slot,
+      preflight.as_ref().map(|preflight| &preflight.slot.item),
+      credits,
+    ),
+  );
+  match format {
+    OutputFormat::Plain => println!("Item dropped! Your new balance is {}", credits),
+    OutputFormat::Json => OutputFormat::print_json(&DropOutput {
+      machine,
+      slot,
+      credits,
+    }),
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &["machine", "slot", "credits"],
+      &[vec![machine, slot.to_string(), credits.to_string()]],
+    ),
+  }
   Ok(())
 }
+
+fn print_dry_run(machine: String, slot: u8, preflight: Option<Preflight>, format: OutputFormat) {
+  let output = DryRunOutput {
+    item: preflight
+      .as_ref()
+      .map(|preflight| preflight.slot.item.name.clone()),
+    price: preflight
+      .as_ref()
+      .map(|preflight| preflight.slot.item.price),
+    credits: preflight.as_ref().map(|preflight| preflight.credits),
+    projected_credits: preflight.as_ref().map(Preflight::projected_credits),
+    machine,
+    slot,
+  };
+  match format {
+    OutputFormat::Plain => match preflight {
+      Some(preflight) => println!(
+        "Would drop {} ({} Credits) from {} slot {}. Your balance would go from {} to {}",
+        preflight.slot.item.name,
+        preflight.slot.item.price,
+        preflight.machine.display_name,
+        output.slot,
+        preflight.credits,
+        preflight.projected_credits()
+      ),
+      None => println!(
+        "Would drop whatever is in {} slot {}",
+        output.machine, output.slot
+      ),
+    },
+    OutputFormat::Json => OutputFormat::print_json(&output),
+    OutputFormat::Csv | OutputFormat::Tsv => {
+      let optional = |value: Option<String>| value.unwrap_or_default();
+      format.print_table(
+        &[
+          "machine",
+          "slot",
+          "item",
+          "price",
+          "credits",
+          "projected_credits",
+        ],
+        &[vec![
+          output.machine,
+          output.slot.to_string(),
+          optional(output.item),
+          optional(output.price.map(|price| price.to_string())),
+          optional(output.credits.map(|credits| credits.to_string())),
+          optional(output.projected_credits.map(|credits| credits.to_string())),
+        ]],
+      )
+    }
+  }
+}
+
+/// What the pre-flight checks found out
+pub struct Preflight {
+  pub machine: Machine,
+  pub slot: Slot,
+  pub credits: i64,
+}
+
+impl Preflight {
+  /// The balance left over after dropping
+  pub fn projected_credits(&self) -> i64 {
+    self.credits - self.slot.item.price as i64
+  }
+}
+
+/// Looks up the machine, slot and balance a drop would involve
+pub fn resolve(api: &dyn DrinkBackend, machine: &str, slot: u8) -> Result<Preflight, APIError> {
+  let drinks = api.get_status_for_machine(Some(machine))?;
+  let machine = drinks
+    .machines
+    .into_iter()
+    .find(|candidate| candidate.name == machine)
+    .ok_or_else(|| APIError::NotFound(format!("There's no machine named {}", machine)))?;
+  let slot = machine
+    .slots
+    .iter()
+    .find(|candidate| candidate.number == slot)
+    .cloned()
+    .ok_or_else(|| {
+      APIError::NotFound(format!(
+        "{} doesn't have a slot {}",
+        machine.display_name, slot
+      ))
+    })?;
+  let credits = api.get_credits()?;
+  Ok(Preflight {
+    machine,
+    slot,
+    credits,
+  })
+}
+
+/// Checks everything we can before spending anyone's credits, so the user
+/// gets a clear explanation instead of whatever the server says
+pub fn preflight(api: &dyn DrinkBackend, machine: &str, slot: u8) -> Result<Preflight, APIError> {
+  let preflight = resolve(api, machine, slot)?;
+  let (machine, slot) = (&preflight.machine, &preflight.slot);
+  if !machine.is_online {
+    return Err(APIError::MachineOffline(format!(
+      "{} is offline",
+      machine.display_name
+    )));
+  }
+  if !slot.active {
+    return Err(APIError::SlotUnavailable(format!(
+      "Slot {} of {} is disabled",
+      slot.number, machine.display_name
+    )));
+  }
+  if slot.empty || slot.count == Some(0) {
+    return Err(APIError::SlotUnavailable(format!(
+      "Slot {} of {} ({}) is empty",
+      slot.number, machine.display_name, slot.item.name
+    )));
+  }
+  if preflight.projected_credits() < 0 {
+    return Err(APIError::InsufficientCredits(format!(
+      "{} costs {} credits, but you only have {}",
+      slot.item.name, slot.item.price, preflight.credits
+    )));
+  }
+  Ok(preflight)
+}
+
+/// Finds a machine stocking something that looks like `item` and drops it
+pub fn drop_item(
+  api: &dyn DrinkBackend,
+  item: String,
+  options: &DropOptions,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let drinks = api.get_status_for_machine(None)?;
+  let candidates = find_item(&drinks.machines, &item);
+  let (machine, slot) = match candidates.len() {
+    0 => {
+      return Err(APIError::NotFound(format!(
+        "No online machine has anything like \"{}\" in stock",
+        item
+      )))
+    }
+    1 => candidates[0],
+    _ => choose_candidate(&item, &candidates)?,
+  };
+  eprintln!(
+    "Found {} in {} (slot {})",
+    slot.item.name, machine.display_name, slot.number
+  );
+  drop(api, machine.name.clone(), slot.number, options, format)
+}
+
+/// Lowercases and strips everything but letters and numbers, so
+/// "Cherry Coke" and "cherry-coke" compare equal
+fn normalize(name: &str) -> String {
+  name
+    .chars()
+    .filter(|c| c.is_alphanumeric())
+    .flat_map(char::to_lowercase)
+    .collect()
+}
+
+/// How well `name` matches `query`, lower is better. None means no match at all
+fn match_score(query: &str, name: &str) -> Option<u8> {
+  let name = normalize(name);
+  if name == query {
+    Some(0)
+  } else if name.starts_with(query) {
+    Some(1)
+  } else if name.contains(query) {
+    Some(2)
+  } else {
+    // Every character of the query shows up in order, e.g. "chcoke"
+    let mut chars = name.chars();
+    match query.chars().all(|q| chars.any(|c| c == q)) {
+      true => Some(3),
+      false => None,
+    }
+  }
+}
+
+/// Whether `name` looks anything like `query`, with the same fuzziness as `--item`
+pub fn item_matches(query: &str, name: &str) -> bool {
+  let query = normalize(query);
+  !query.is_empty() && match_score(&query, name).is_some()
+}
+
+/// Every droppable slot whose item best matches `query`
+fn find_item<'a>(machines: &'a [Machine], query: &str) -> Vec<(&'a Machine, &'a Slot)> {
+  let query = normalize(query);
+  if query.is_empty() {
+    return vec![];
+  }
+  let matches: Vec<(u8, &Machine, &Slot)> = machines
+    .iter()
+    .filter(|machine| machine.is_online)
+    .flat_map(|machine| machine.slots.iter().map(move |slot| (machine, slot)))
+    .filter(|(_, slot)| slot.active && !slot.empty && slot.count != Some(0))
+    .filter_map(|(machine, slot)| {
+      match_score(&query, &slot.item.name).map(|score| (score, machine, slot))
+    })
+    .collect();
+  let best = match matches.iter().map(|(score, _, _)| *score).min() {
+    Some(best) => best,
+    None => return vec![],
+  };
+  matches
+    .into_iter()
+    .filter(|(score, _, _)| *score == best)
+    .map(|(_, machine, slot)| (machine, slot))
+    .collect()
+}
+
+/// Asks the user which of several matching slots they meant
+fn choose_candidate<'a>(
+  query: &str,
+  candidates: &[(&'a Machine, &'a Slot)],
+) -> Result<(&'a Machine, &'a Slot), APIError> {
+  eprintln!("Several slots match \"{}\":", query);
+  for (index, (machine, slot)) in candidates.iter().enumerate() {
+    eprintln!(
+      "  {}. {} slot {}: {} ({} Credits)",
+      index + 1,
+      machine.display_name,
+      slot.number,
+      slot.item.name,
+      slot.item.price
+    );
+  }
+  let stdin = io::stdin();
+  loop {
+    eprint!("Which one? [1-{}]: ", candidates.len());
+    io::stderr().flush().ok();
+    let mut line = String::new();
+    if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+      return Err(APIError::Aborted);
+    }
+    match line.trim().parse::<usize>() {
+      Ok(choice) if (1..=candidates.len()).contains(&choice) => {
+        return Ok(candidates[choice - 1]);
+      }
+      _ => eprintln!("Please enter a number between 1 and {}", candidates.len()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::mock::{MockBackend, Operation};
+
+  const OPTIONS: DropOptions = DropOptions {
+    force: false,
+    dry_run: false,
+    confirm: false,
+  };
+
+  #[test]
+  fn preflight_explains_problems() {
+    let backend = MockBackend::sample();
+    assert!(preflight(&backend, "bigdrink", 1).is_ok());
+    assert!(matches!(
+      preflight(&backend, "bigdrink", 3),
+      Err(APIError::SlotUnavailable(_))
+    ));
+    assert!(matches!(
+      preflight(&backend, "bigdrink", 4),
+      Err(APIError::SlotUnavailable(_))
+    ));
+    assert!(matches!(
+      preflight(&backend, "bigdrink", 9),
+      Err(APIError::NotFound(_))
+    ));
+    assert!(matches!(
+      preflight(&backend, "littledrink", 1),
+      Err(APIError::MachineOffline(_))
+    ));
+    backend.with_state(|state| state.balance = 10);
+    assert!(matches!(
+      preflight(&backend, "bigdrink", 1),
+      Err(APIError::InsufficientCredits(_))
+    ));
+  }
+
+  #[test]
+  fn drop_only_charges_after_preflight() {
+    let backend = MockBackend::sample();
+    let format = OutputFormat::Plain;
+    drop(&backend, "bigdrink".to_string(), 1, &OPTIONS, format).unwrap();
+    assert_eq!(backend.balance(), 50);
+    assert!(drop(&backend, "bigdrink".to_string(), 3, &OPTIONS, format).is_err());
+    backend.fail_next(Operation::Status, APIError::BadFormat);
+    assert!(drop(&backend, "bigdrink".to_string(), 1, &OPTIONS, format).is_err());
+    assert_eq!(backend.with_state(|state| state.drops.len()), 1);
+  }
+
+  #[test]
+  fn dry_run_doesnt_drop() {
+    let backend = MockBackend::sample();
+    let options = DropOptions {
+      dry_run: true,
+      ..OPTIONS
+    };
+    drop(
+      &backend,
+      "bigdrink".to_string(),
+      1,
+      &options,
+      OutputFormat::Json,
+    )
+    .unwrap();
+    assert_eq!(backend.balance(), 100);
+    assert!(backend.with_state(|state| state.drops.is_empty()));
+  }
+
+  #[test]
+  fn find_item_prefers_closest_match() {
+    let backend = MockBackend::sample();
+    let machines = backend.with_state(|state| state.machines.clone());
+    let found = find_item(&machines, "coke");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].1.item.name, "Coke");
+    // Little Drink is offline, so only Big Drink's Cherry Coke counts
+    let found = find_item(&machines, "CHERRY-coke");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0.name, "bigdrink");
+    assert_eq!(find_item(&machines, "chcoke").len(), 1);
+    // Empty and disabled slots never match
+    assert!(find_item(&machines, "water").is_empty());
+    assert!(find_item(&machines, "root beer").is_empty());
+  }
+}
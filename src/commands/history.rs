@@ -0,0 +1,267 @@
+use crate::api::APIError;
+use crate::history::{self, Entry};
+use crate::output::{self, OutputFormat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use time::macros::format_description;
+use time::{Date, Duration, OffsetDateTime};
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Summary {
+  pub drops: usize,
+  /// Credits spent on drops whose price we know
+  pub total_spent: u64,
+  pub most_frequent_item: Option<FrequentItem>,
+  /// Only weeks with at least one drop, oldest first
+  pub weeks: Vec<Week>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FrequentItem {
+  pub item: String,
+  pub drops: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Week {
+  /// The Monday the week starts on
+  pub week: String,
+  pub drops: usize,
+  pub spent: u64,
+}
+
+/// Parses a `YYYY-MM-DD` date for `--since` and `--until`
+pub fn parse_date(value: &str) -> Result<Date, String> {
+  Date::parse(value, format_description!("[year]-[month]-[day]"))
+    .map_err(|_| format!("\"{}\" isn't a YYYY-MM-DD date", value))
+}
+
+fn format_date(date: Date) -> String {
+  // panic rationale: a plain date always fits this format
+  date
+    .format(format_description!("[year]-[month]-[day]"))
+    .unwrap()
+}
+
+fn date_time(timestamp: u64) -> OffsetDateTime {
+  OffsetDateTime::from_unix_timestamp(timestamp as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+fn week_of(date: Date) -> Date {
+  date - Duration::days(date.weekday().number_days_from_monday() as i64)
+}
+
+/// Entries dropped between `since` and `until`, both inclusive
+pub fn filter(entries: Vec<Entry>, since: Option<Date>, until: Option<Date>) -> Vec<Entry> {
+  entries
+    .into_iter()
+    .filter(|entry| {
+      let date = date_time(entry.timestamp).date();
+      since.map(|since| date >= since).unwrap_or(true)
+        && until.map(|until| date <= until).unwrap_or(true)
+    })
+    .collect()
+}
+
+pub fn summarize(entries: &[Entry]) -> Summary {
+  let mut items: BTreeMap<&str, usize> = BTreeMap::new();
+  let mut weeks: BTreeMap<Date, (usize, u64)> = BTreeMap::new();
+  for entry in entries {
+    if let Some(item) = &entry.item {
+      *items.entry(item).or_default() += 1;
+    }
+    let week = weeks
+      .entry(week_of(date_time(entry.timestamp).date()))
+      .or_default();
+    week.0 += 1;
+    week.1 += entry.price.unwrap_or(0);
+  }
+  Summary {
+    drops: entries.len(),
+    total_spent: entries.iter().filter_map(|entry| entry.price).sum(),
+    // Ties go to whichever item comes first alphabetically
+    most_frequent_item: items
+      .into_iter()
+      .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+      .map(|(item, drops)| FrequentItem {
+        item: item.to_string(),
+        drops,
+      }),
+    weeks: weeks
+      .into_iter()
+      .map(|(week, (drops, spent))| Week {
+        week: format_date(week),
+        drops,
+        spent,
+      })
+      .collect(),
+  }
+}
+
+/// Lists past drops, or summarizes them with `summary`. Dates are in UTC
+pub fn history(
+  path: Option<&Path>,
+  since: Option<Date>,
+  until: Option<Date>,
+  summary: bool,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let entries = match path {
+    Some(path) => history::read(path)?,
+    None => vec![],
+  };
+  let entries = filter(entries, since, until);
+  match summary {
+    true => print_summary(&summarize(&entries), format),
+    false => print_entries(&entries, format),
+  }
+  Ok(())
+}
+
+fn print_entries(entries: &[Entry], format: OutputFormat) {
+  let optional = |value: Option<String>| value.unwrap_or_default();
+  match format {
+    OutputFormat::Plain if entries.is_empty() => println!("No drops recorded"),
+    OutputFormat::Plain => {
+      for entry in entries {
+        println!(
+          "{} {} slot {}: {}{} (balance {})",
+          output::format_timestamp(entry.timestamp),
+          entry.machine,
+          entry.slot,
+          entry.item.as_deref().unwrap_or("unknown item"),
+          optional(entry.price.map(|price| format!(", {} Credits", price))),
+          entry.balance
+        );
+      }
+    }
+    OutputFormat::Json => OutputFormat::print_json(&entries),
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &["timestamp", "machine", "slot", "item", "price", "balance"],
+      &entries
+        .iter()
+        .map(|entry| {
+          vec![
+            entry.timestamp.to_string(),
+            entry.machine.clone(),
+            entry.slot.to_string(),
+            optional(entry.item.clone()),
+            optional(entry.price.map(|price| price.to_string())),
+            entry.balance.to_string(),
+          ]
+        })
+        .collect::<Vec<_>>(),
+    ),
+  }
+}
+
+fn print_summary(summary: &Summary, format: OutputFormat) {
+  match format {
+    OutputFormat::Plain => {
+      println!(
+        "{} drops, {} credits spent",
+        summary.drops, summary.total_spent
+      );
+      if let Some(item) = &summary.most_frequent_item {
+        println!("Most frequent: {} ({} drops)", item.item, item.drops);
+      }
+      if !summary.weeks.is_empty() {
+        println!("Per week:");
+      }
+      for week in &summary.weeks {
+        println!(
+          "  {}  {} drops, {} credits",
+          week.week, week.drops, week.spent
+        );
+      }
+    }
+    OutputFormat::Json => OutputFormat::print_json(summary),
+    // Tables only fit the per-week breakdown, which adds up to the totals anyway
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &["week", "drops", "spent"],
+      &summary
+        .weeks
+        .iter()
+        .map(|week| {
+          vec![
+            week.week.clone(),
+            week.drops.to_string(),
+            week.spent.to_string(),
+          ]
+        })
+        .collect::<Vec<_>>(),
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entry(date: &str, item: Option<&str>, price: Option<u64>) -> Entry {
+    let timestamp = parse_date(date)
+      .unwrap()
+      .midnight()
+      .assume_utc()
+      .unix_timestamp() as u64;
+    Entry {
+      timestamp: timestamp + 12 * 60 * 60,
+      machine: "bigdrink".to_string(),
+      slot: 1,
+      item: item.map(str::to_string),
+      price,
+      balance: 0,
+    }
+  }
+
+  #[test]
+  fn filters_inclusively() {
+    let entries = vec![
+      entry("2023-07-01", Some("Coke"), Some(50)),
+      entry("2023-07-02", Some("Coke"), Some(50)),
+      entry("2023-07-03", Some("Coke"), Some(50)),
+    ];
+    let filtered = filter(
+      entries.clone(),
+      Some(parse_date("2023-07-02").unwrap()),
+      Some(parse_date("2023-07-03").unwrap()),
+    );
+    assert_eq!(filtered, entries[1..]);
+    assert!(parse_date("July 2nd").is_err());
+  }
+
+  #[test]
+  fn summarizes() {
+    let summary = summarize(&[
+      // Sunday, then Monday and Tuesday of the next week
+      entry("2023-07-02", Some("Water"), Some(25)),
+      entry("2023-07-03", Some("Coke"), Some(50)),
+      entry("2023-07-04", Some("Coke"), Some(50)),
+      entry("2023-07-04", None, None),
+    ]);
+    assert_eq!(
+      summary,
+      Summary {
+        drops: 4,
+        total_spent: 125,
+        most_frequent_item: Some(FrequentItem {
+          item: "Coke".to_string(),
+          drops: 2,
+        }),
+        weeks: vec![
+          Week {
+            week: "2023-06-26".to_string(),
+            drops: 1,
+            spent: 25,
+          },
+          Week {
+            week: "2023-07-03".to_string(),
+            drops: 3,
+            spent: 100,
+          },
+        ],
+      }
+    );
+  }
+}
@@ -1,8 +1,87 @@
-use crate::api::{APIError, API};
+use crate::api::{APIError, DrinkList};
+use crate::backend::DrinkBackend;
+use crate::cache::Snapshot;
+use crate::output::OutputFormat;
 
-pub fn list(api: &mut API, machine: Option<String>) -> Result<(), APIError> {
-  let drinks = api.get_status_for_machine(machine.as_deref())?;
+pub fn list(
+  api: &dyn DrinkBackend,
+  machine: Option<String>,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  print(fetch(api, machine.as_deref())?, format);
+  Ok(())
+}
 
+/// Lists `snapshot`, the last machine list saved, without asking the server
+pub fn list_offline(
+  snapshot: Option<Snapshot>,
+  machine: Option<String>,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  let snapshot = snapshot.ok_or(APIError::NoSnapshot)?;
+  eprintln!("Offline, showing machines as of {}", snapshot.as_of());
+  print(from_snapshot(snapshot, machine.as_deref())?, format);
+  Ok(())
+}
+
+fn print(drinks: DrinkList, format: OutputFormat) {
+  match format {
+    OutputFormat::Plain => print_plain(drinks),
+    OutputFormat::Json => OutputFormat::print_json(&drinks),
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &[
+        "machine",
+        "machine_display_name",
+        "machine_online",
+        "slot",
+        "item_id",
+        "item_name",
+        "price",
+        "active",
+        "empty",
+        "count",
+      ],
+      &table_rows(&drinks),
+    ),
+  }
+}
+
+/// Asks the server for `machine`, or every machine, falling back to the last
+/// list we saw when the server can't be reached
+fn fetch(api: &dyn DrinkBackend, machine: Option<&str>) -> Result<DrinkList, APIError> {
+  match api.get_status_for_machine(machine) {
+    Err(err) if err.is_unreachable() => match api.cached_status() {
+      Some(snapshot) => {
+        eprintln!("Warning: {}", err);
+        eprintln!(
+          "Showing machines as of {}, which may be stale",
+          snapshot.as_of()
+        );
+        from_snapshot(snapshot, machine)
+      }
+      None => Err(err),
+    },
+    result => result,
+  }
+}
+
+fn from_snapshot(snapshot: Snapshot, machine: Option<&str>) -> Result<DrinkList, APIError> {
+  let mut drinks = snapshot.drinks;
+  if let Some(machine) = machine {
+    drinks
+      .machines
+      .retain(|candidate| candidate.name == machine);
+    if drinks.machines.is_empty() {
+      return Err(APIError::NotFound(format!(
+        "No machine named {} in the saved machine list",
+        machine
+      )));
+    }
+  }
+  Ok(drinks)
+}
+
+fn print_plain(drinks: DrinkList) {
   for machine in drinks.machines {
     println!();
     let subject_line = format!("{} ({})", machine.display_name, machine.name);
@@ -17,5 +96,68 @@ pub fn list(api: &mut API, machine: Option<String>) -> Result<(), APIError> {
       println!();
     }
   }
-  Ok(())
+}
+
+/// One row per slot, with the machine flattened into it
+fn table_rows(drinks: &DrinkList) -> Vec<Vec<String>> {
+  drinks
+    .machines
+    .iter()
+    .flat_map(|machine| {
+      machine.slots.iter().map(move |slot| {
+        vec![
+          machine.name.clone(),
+          machine.display_name.clone(),
+          machine.is_online.to_string(),
+          slot.number.to_string(),
+          slot.item.id.to_string(),
+          slot.item.name.clone(),
+          slot.item.price.to_string(),
+          slot.active.to_string(),
+          slot.empty.to_string(),
+          slot
+            .count
+            .map(|count| count.to_string())
+            .unwrap_or_default(),
+        ]
+      })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::mock::{MockBackend, Operation};
+
+  #[test]
+  fn falls_back_to_the_last_list() {
+    let backend = MockBackend::sample();
+    backend.fail_next(
+      Operation::Status,
+      APIError::SsoFailure("SSO is down".to_string()),
+    );
+    assert!(matches!(
+      fetch(&backend, None),
+      Err(APIError::SsoFailure(_))
+    ));
+    fetch(&backend, None).unwrap();
+    backend.fail_next(
+      Operation::Status,
+      APIError::SsoFailure("SSO is down".to_string()),
+    );
+    let drinks = fetch(&backend, Some("littledrink")).unwrap();
+    assert_eq!(drinks.machines.len(), 1);
+    assert_eq!(drinks.machines[0].name, "littledrink");
+  }
+
+  #[test]
+  fn only_falls_back_when_unreachable() {
+    let backend = MockBackend::sample();
+    fetch(&backend, None).unwrap();
+    backend.fail_next(Operation::Status, APIError::Unauthorized);
+    assert!(matches!(fetch(&backend, None), Err(APIError::Unauthorized)));
+    let snapshot = backend.cached_status().unwrap();
+    assert_eq!(from_snapshot(snapshot, None).unwrap().machines.len(), 2);
+  }
 }
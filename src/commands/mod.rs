@@ -1,4 +1,10 @@
+pub mod admin;
+pub mod completions;
 pub mod credits;
 pub mod drop;
+pub mod history;
 pub mod list;
+pub mod profile;
 pub mod token;
+pub mod watch;
+pub mod whoami;
@@ -0,0 +1,153 @@
+use crate::api::APIError;
+use crate::config::Config;
+use crate::oidc::LoginFlow;
+use crate::output::OutputFormat;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct ProfileSummary {
+  name: String,
+  active: bool,
+  api_url: String,
+}
+
+#[derive(Serialize)]
+struct ProfileDetails {
+  name: String,
+  api_url: String,
+  oidc_issuer: String,
+  client_id: String,
+  scopes: String,
+  kerberos_realm: String,
+  kerberos_cache: Option<String>,
+  login: LoginFlow,
+  token_file: Option<String>,
+  username: Option<String>,
+  machine: Option<String>,
+  format: OutputFormat,
+  confirm: bool,
+  token_cache: Option<String>,
+  timeout: u64,
+  connect_timeout: u64,
+  retries: u32,
+  proxy: Option<String>,
+  ca_cert: Option<String>,
+}
+
+/// Lists every profile in `file`, marking the `active` one
+pub fn list(file: &Config, active: &str, format: OutputFormat) -> Result<(), APIError> {
+  let profiles = file
+    .profile_names()
+    .into_iter()
+    .map(|name| {
+      let api_url = file.resolve(&name)?.api_config().api_base_url;
+      Ok(ProfileSummary {
+        active: name == active,
+        name,
+        api_url,
+      })
+    })
+    .collect::<Result<Vec<_>, APIError>>()?;
+
+  match format {
+    OutputFormat::Plain => {
+      for profile in profiles {
+        let marker = if profile.active { "*" } else { " " };
+        println!("{} {} ({})", marker, profile.name, profile.api_url);
+      }
+    }
+    OutputFormat::Json => OutputFormat::print_json(&profiles),
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &["name", "active", "api_url"],
+      &profiles
+        .into_iter()
+        .map(|profile| vec![profile.name, profile.active.to_string(), profile.api_url])
+        .collect::<Vec<_>>(),
+    ),
+  }
+  Ok(())
+}
+
+/// Shows the settings `config` resolves to
+pub fn show(config: &Config, format: OutputFormat) -> Result<(), APIError> {
+  let api_config = config.api_config();
+  let details = ProfileDetails {
+    name: config.profile_name().to_string(),
+    api_url: api_config.api_base_url,
+    oidc_issuer: api_config.oidc_issuer,
+    client_id: api_config.client_id,
+    scopes: api_config.scopes,
+    kerberos_realm: api_config.kerberos_realm,
+    kerberos_cache: api_config
+      .kerberos_cache
+      .map(|path| path.display().to_string()),
+    login: api_config.login,
+    token_file: api_config.token_file.map(|path| path.display().to_string()),
+    username: api_config.username,
+    machine: config.machine.clone(),
+    format: config.format(),
+    confirm: config.confirm(),
+    token_cache: api_config
+      .token_cache
+      .map(|path| path.display().to_string()),
+    timeout: api_config.timeout.as_secs(),
+    connect_timeout: api_config.connect_timeout.as_secs(),
+    retries: api_config.retries,
+    proxy: api_config.proxy,
+    ca_cert: api_config.ca_cert.map(|path| path.display().to_string()),
+  };
+
+  match format {
+    OutputFormat::Json => OutputFormat::print_json(&details),
+    _ => {
+      let rows = vec![
+        vec!["name".to_string(), details.name],
+        vec!["api_url".to_string(), details.api_url],
+        vec!["oidc_issuer".to_string(), details.oidc_issuer],
+        vec!["client_id".to_string(), details.client_id],
+        vec!["scopes".to_string(), details.scopes],
+        vec!["kerberos_realm".to_string(), details.kerberos_realm],
+        vec![
+          "kerberos_cache".to_string(),
+          details.kerberos_cache.unwrap_or_default(),
+        ],
+        vec![
+          "login".to_string(),
+          format!("{:?}", details.login).to_lowercase(),
+        ],
+        vec![
+          "token_file".to_string(),
+          details.token_file.unwrap_or_default(),
+        ],
+        vec!["username".to_string(), details.username.unwrap_or_default()],
+        vec!["machine".to_string(), details.machine.unwrap_or_default()],
+        vec![
+          "format".to_string(),
+          format!("{:?}", details.format).to_lowercase(),
+        ],
+        vec!["confirm".to_string(), details.confirm.to_string()],
+        vec![
+          "token_cache".to_string(),
+          details.token_cache.unwrap_or_default(),
+        ],
+        vec!["timeout".to_string(), details.timeout.to_string()],
+        vec![
+          "connect_timeout".to_string(),
+          details.connect_timeout.to_string(),
+        ],
+        vec!["retries".to_string(), details.retries.to_string()],
+        vec!["proxy".to_string(), details.proxy.unwrap_or_default()],
+        vec!["ca_cert".to_string(), details.ca_cert.unwrap_or_default()],
+      ];
+      match format {
+        OutputFormat::Plain => {
+          for row in rows {
+            println!("{}: {}", row[0], row[1]);
+          }
+        }
+        _ => format.print_table(&["setting", "value"], &rows),
+      }
+    }
+  }
+  Ok(())
+}
@@ -1,6 +1,7 @@
-use crate::api::{APIError, API};
+use crate::api::APIError;
+use crate::backend::DrinkBackend;
 
-pub fn token(api: &mut API) -> Result<(), APIError> {
+pub fn token(api: &dyn DrinkBackend) -> Result<(), APIError> {
   println!("{}", api.get_token()?);
 
   Ok(())
@@ -0,0 +1,321 @@
+use crate::api::{APIError, DrinkList, Machine, Slot};
+use crate::backend::DrinkBackend;
+use crate::cache;
+use crate::commands::drop::item_matches;
+use crate::output::OutputFormat;
+use serde::Serialize;
+use std::thread;
+use std::time::Duration;
+
+/// Something that changed between two polls
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+  MachineOnline {
+    machine: String,
+  },
+  MachineOffline {
+    machine: String,
+  },
+  SlotRestocked {
+    machine: String,
+    slot: u8,
+    item: String,
+    count: Option<u64>,
+  },
+  SlotEmptied {
+    machine: String,
+    slot: u8,
+    item: String,
+  },
+  ItemChanged {
+    machine: String,
+    slot: u8,
+    old_item: String,
+    item: String,
+  },
+  PriceChanged {
+    machine: String,
+    slot: u8,
+    item: String,
+    old_price: u64,
+    price: u64,
+  },
+}
+
+#[derive(Serialize)]
+struct EventLine<'a> {
+  /// Unix timestamp of the poll that noticed the change
+  timestamp: u64,
+  #[serde(flatten)]
+  event: &'a Event,
+}
+
+impl Event {
+  fn describe(&self) -> String {
+    match self {
+      Event::MachineOnline { machine } => format!("{} is back online", machine),
+      Event::MachineOffline { machine } => format!("{} went offline", machine),
+      Event::SlotRestocked {
+        machine,
+        slot,
+        item,
+        count,
+      } => match count {
+        Some(count) => format!(
+          "{} slot {}: {} restocked ({} left)",
+          machine, slot, item, count
+        ),
+        None => format!("{} slot {}: {} restocked", machine, slot, item),
+      },
+      Event::SlotEmptied {
+        machine,
+        slot,
+        item,
+      } => format!("{} slot {}: {} is empty", machine, slot, item),
+      Event::ItemChanged {
+        machine,
+        slot,
+        old_item,
+        item,
+      } => format!(
+        "{} slot {}: {} was replaced by {}",
+        machine, slot, old_item, item
+      ),
+      Event::PriceChanged {
+        machine,
+        slot,
+        item,
+        old_price,
+        price,
+      } => format!(
+        "{} slot {}: {} now costs {} credits (was {})",
+        machine, slot, item, price, old_price
+      ),
+    }
+  }
+
+  /// The item an event is about, machine events aren't about any one item
+  fn items(&self) -> Vec<&str> {
+    match self {
+      Event::MachineOnline { .. } | Event::MachineOffline { .. } => vec![],
+      Event::SlotRestocked { item, .. }
+      | Event::SlotEmptied { item, .. }
+      | Event::PriceChanged { item, .. } => vec![item],
+      Event::ItemChanged { old_item, item, .. } => vec![old_item, item],
+    }
+  }
+}
+
+fn is_empty(slot: &Slot) -> bool {
+  slot.empty || slot.count == Some(0)
+}
+
+/// Every change between two snapshots of the same machines
+pub fn diff(old: &DrinkList, new: &DrinkList) -> Vec<Event> {
+  let mut events = vec![];
+  for machine in &new.machines {
+    let old_machine = match old.machines.iter().find(|old| old.id == machine.id) {
+      Some(old_machine) => old_machine,
+      None => continue,
+    };
+    let name = || machine.name.clone();
+    match (old_machine.is_online, machine.is_online) {
+      (false, true) => events.push(Event::MachineOnline { machine: name() }),
+      (true, false) => events.push(Event::MachineOffline { machine: name() }),
+      _ => {}
+    }
+    for slot in &machine.slots {
+      let old_slot = match old_machine
+        .slots
+        .iter()
+        .find(|old| old.number == slot.number)
+      {
+        Some(old_slot) => old_slot,
+        None => continue,
+      };
+      if old_slot.item.id != slot.item.id {
+        events.push(Event::ItemChanged {
+          machine: name(),
+          slot: slot.number,
+          old_item: old_slot.item.name.clone(),
+          item: slot.item.name.clone(),
+        });
+      } else if old_slot.item.price != slot.item.price {
+        events.push(Event::PriceChanged {
+          machine: name(),
+          slot: slot.number,
+          item: slot.item.name.clone(),
+          old_price: old_slot.item.price,
+          price: slot.item.price,
+        });
+      }
+      let restocked = match (is_empty(old_slot), is_empty(slot)) {
+        (true, false) => true,
+        (false, false) => slot.count > old_slot.count,
+        _ => false,
+      };
+      if restocked {
+        events.push(Event::SlotRestocked {
+          machine: name(),
+          slot: slot.number,
+          item: slot.item.name.clone(),
+          count: slot.count,
+        });
+      } else if !is_empty(old_slot) && is_empty(slot) {
+        events.push(Event::SlotEmptied {
+          machine: name(),
+          slot: slot.number,
+          item: slot.item.name.clone(),
+        });
+      }
+    }
+  }
+  events
+}
+
+/// Whether `event` is about `item`. Machine events count if the machine carries it
+fn matches(event: &Event, item: &str, machines: &[Machine]) -> bool {
+  match event {
+    Event::MachineOnline { machine } | Event::MachineOffline { machine } => machines
+      .iter()
+      .filter(|candidate| &candidate.name == machine)
+      .flat_map(|candidate| &candidate.slots)
+      .any(|slot| item_matches(item, &slot.item.name)),
+    _ => event
+      .items()
+      .into_iter()
+      .any(|name| item_matches(item, name)),
+  }
+}
+
+/// Polls forever, printing whatever changed. Json prints one event per line,
+/// every other format prints human-readable lines
+pub fn watch(
+  api: &dyn DrinkBackend,
+  machine: Option<String>,
+  item: Option<String>,
+  interval: Duration,
+  format: OutputFormat,
+) -> Result<(), APIError> {
+  // Fail fast if the first poll doesn't work, it probably never will
+  let mut previous = api.get_status_for_machine(machine.as_deref())?;
+  if format == OutputFormat::Plain {
+    eprintln!(
+      "Watching {}, press Ctrl-C to stop",
+      machine.as_deref().unwrap_or("every machine")
+    );
+  }
+  loop {
+    thread::sleep(interval);
+    let current = match api.get_status_for_machine(machine.as_deref()) {
+      Ok(current) => current,
+      Err(err) => {
+        // Keep watching through blips, the next poll will catch up
+        eprintln!("Couldn't poll: {}", err);
+        continue;
+      }
+    };
+    for event in diff(&previous, &current) {
+      if let Some(item) = &item {
+        if !matches(&event, item, &current.machines) {
+          continue;
+        }
+      }
+      match format {
+        OutputFormat::Json => OutputFormat::print_json(&EventLine {
+          timestamp: cache::now(),
+          event: &event,
+        }),
+        _ => println!("{}", event.describe()),
+      }
+    }
+    previous = current;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::mock::MockBackend;
+
+  fn snapshot(backend: &MockBackend) -> DrinkList {
+    backend.get_status_for_machine(None).unwrap()
+  }
+
+  #[test]
+  fn nothing_changed() {
+    let backend = MockBackend::sample();
+    assert_eq!(diff(&snapshot(&backend), &snapshot(&backend)), vec![]);
+  }
+
+  #[test]
+  fn notices_changes() {
+    let backend = MockBackend::sample();
+    let before = snapshot(&backend);
+    backend.with_state(|state| {
+      let big_drink = &mut state.machines[0];
+      // Coke sells out, water gets restocked, cherry coke gets swapped out
+      big_drink.slots[0].count = Some(0);
+      big_drink.slots[0].empty = true;
+      big_drink.slots[2].count = Some(6);
+      big_drink.slots[2].empty = false;
+      big_drink.slots[2].item.price = 30;
+      big_drink.slots[1].item.id = 9;
+      big_drink.slots[1].item.name = "Sprite".to_string();
+      state.machines[1].is_online = true;
+    });
+    let events = diff(&before, &snapshot(&backend));
+    let machine = || "bigdrink".to_string();
+    assert_eq!(
+      events,
+      vec![
+        Event::SlotEmptied {
+          machine: machine(),
+          slot: 1,
+          item: "Coke".to_string(),
+        },
+        Event::ItemChanged {
+          machine: machine(),
+          slot: 2,
+          old_item: "Cherry Coke".to_string(),
+          item: "Sprite".to_string(),
+        },
+        Event::PriceChanged {
+          machine: machine(),
+          slot: 3,
+          item: "Water".to_string(),
+          old_price: 25,
+          price: 30,
+        },
+        Event::SlotRestocked {
+          machine: machine(),
+          slot: 3,
+          item: "Water".to_string(),
+          count: Some(6),
+        },
+        Event::MachineOnline {
+          machine: "littledrink".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn filters_by_item() {
+    let backend = MockBackend::sample();
+    let machines = snapshot(&backend).machines;
+    let online = Event::MachineOnline {
+      machine: "littledrink".to_string(),
+    };
+    assert!(matches(&online, "cherry coke", &machines));
+    assert!(!matches(&online, "water", &machines));
+    let emptied = Event::SlotEmptied {
+      machine: "bigdrink".to_string(),
+      slot: 1,
+      item: "Coke".to_string(),
+    };
+    assert!(matches(&emptied, "coke", &machines));
+    assert!(!matches(&emptied, "water", &machines));
+  }
+}
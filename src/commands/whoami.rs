@@ -0,0 +1,166 @@
+use crate::api::{APIError, User};
+use crate::auth;
+use crate::backend::DrinkBackend;
+use crate::cache;
+use crate::jwt;
+use crate::output::{self, OutputFormat};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+
+/// The identity claims Keycloak copies into the access token as well as the id_token
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Claims {
+  exp: Option<u64>,
+  preferred_username: Option<String>,
+  name: Option<String>,
+  email: Option<String>,
+  groups: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct WhoamiOutput {
+  username: String,
+  name: Option<String>,
+  email: Option<String>,
+  groups: Vec<String>,
+  credits: i64,
+  /// Unix timestamp
+  token_expires_at: Option<u64>,
+  kerberos_principal: Option<String>,
+}
+
+/// The default principal in the Kerberos credentials cache `ccache`, or the
+/// default one if `None`, if there is one
+pub fn kerberos_principal(ccache: Option<&Path>) -> Option<String> {
+  let output = auth::kerberos_command("klist", ccache)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  // MIT says "Default principal:", Heimdal says "Principal:"
+  String::from_utf8_lossy(&output.stdout)
+    .lines()
+    .find_map(|line| {
+      line
+        .strip_prefix("Default principal:")
+        .or_else(|| line.trim_start().strip_prefix("Principal:"))
+    })
+    .map(|principal| principal.trim().to_string())
+}
+
+/// Reads what we can out of the token, and only asks the SSO for the rest
+fn identify(api: &dyn DrinkBackend) -> Result<(User, Option<u64>), APIError> {
+  let claims: Claims = jwt::decode_claims(&api.get_token()?).unwrap_or_default();
+  let user = match claims {
+    Claims {
+      preferred_username: Some(preferred_username),
+      name: Some(name),
+      email: Some(email),
+      groups: Some(groups),
+      ..
+    } => User {
+      preferred_username,
+      name: Some(name),
+      email: Some(email),
+      groups,
+    },
+    Claims {
+      ref preferred_username,
+      ref name,
+      ref email,
+      ref groups,
+      ..
+    } => {
+      let info = api.get_user_info()?;
+      User {
+        preferred_username: preferred_username
+          .clone()
+          .unwrap_or(info.preferred_username),
+        name: name.clone().or(info.name),
+        email: email.clone().or(info.email),
+        groups: groups.clone().unwrap_or(info.groups),
+      }
+    }
+  };
+  Ok((user, claims.exp))
+}
+
+fn describe_expiry(expires_at: u64) -> String {
+  let now = cache::now();
+  let when = output::format_timestamp(expires_at);
+  match expires_at.checked_sub(now) {
+    Some(left) => format!("{} (in {} minutes)", when, left / 60),
+    None => format!("{} (expired)", when),
+  }
+}
+
+pub fn whoami(api: &dyn DrinkBackend, format: OutputFormat) -> Result<(), APIError> {
+  let (user, token_expires_at) = identify(api)?;
+  let output = WhoamiOutput {
+    credits: api.get_credits_for(&user.preferred_username)?,
+    username: user.preferred_username,
+    name: user.name,
+    email: user.email,
+    groups: user.groups,
+    token_expires_at,
+    // The user's own ticket if they have one, like logging in does
+    kerberos_principal: kerberos_principal(None).or_else(|| {
+      let ccache = api.kerberos_cache()?;
+      kerberos_principal(Some(&ccache))
+    }),
+  };
+  let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+  match format {
+    OutputFormat::Plain => {
+      println!("Username: {}", output.username);
+      if let Some(name) = &output.name {
+        println!("Name: {}", name);
+      }
+      if let Some(email) = &output.email {
+        println!("Email: {}", email);
+      }
+      if !output.groups.is_empty() {
+        println!("Groups: {}", output.groups.join(", "));
+      }
+      println!("Credits: {}", output.credits);
+      if let Some(expires_at) = output.token_expires_at {
+        println!("Token expires: {}", describe_expiry(expires_at));
+      }
+      println!(
+        "Kerberos principal: {}",
+        output.kerberos_principal.as_deref().unwrap_or("none")
+      );
+    }
+    OutputFormat::Json => OutputFormat::print_json(&output),
+    OutputFormat::Csv | OutputFormat::Tsv => format.print_table(
+      &[
+        "username",
+        "name",
+        "email",
+        "groups",
+        "credits",
+        "token_expires_at",
+        "kerberos_principal",
+      ],
+      &[vec![
+        output.username.clone(),
+        optional(&output.name),
+        optional(&output.email),
+        output.groups.join(" "),
+        output.credits.to_string(),
+        optional(
+          &output
+            .token_expires_at
+            .map(|expires_at| expires_at.to_string()),
+        ),
+        optional(&output.kerberos_principal),
+      ]],
+    ),
+  }
+  Ok(())
+}
@@ -0,0 +1,209 @@
+use crate::api::{APIConfig, APIError};
+use crate::cache;
+use crate::history;
+use crate::oidc::LoginFlow;
+use crate::output::OutputFormat;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The profile used when none is picked, it doesn't need to be defined
+pub const DEFAULT_PROFILE: &str = "prod";
+
+/// Settings from `~/.config/clink/config.toml`. Every field is optional,
+/// anything missing falls through to the next layer or the built-in default
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// Profile to use when `--profile` isn't passed. Once resolved, the active profile
+  pub profile: Option<String>,
+  /// Named sets of settings layered on top of the top-level ones
+  pub profiles: BTreeMap<String, Config>,
+  /// Drink API base URL
+  pub api_url: Option<String>,
+  /// OIDC issuer, e.g. `https://sso.csh.rit.edu/auth/realms/csh`
+  pub oidc_issuer: Option<String>,
+  /// OIDC client id
+  pub client_id: Option<String>,
+  /// Space-separated OIDC scopes
+  pub scopes: Option<String>,
+  /// Kerberos realm to `kinit` into
+  pub kerberos_realm: Option<String>,
+  /// Let kinit write to the default Kerberos credentials cache instead of
+  /// clink's own, defaults to false
+  pub default_ccache: Option<bool>,
+  /// How to log in when there's no cached token
+  pub login: Option<LoginFlow>,
+  /// Token to use instead of logging in
+  pub token: Option<String>,
+  /// File holding a token to use instead of logging in
+  pub token_file: Option<PathBuf>,
+  /// Username to log in as, defaults to the current user
+  pub username: Option<String>,
+  /// Machine to use when a command doesn't name one
+  pub machine: Option<String>,
+  /// Output format to use when `--format` isn't passed
+  pub format: Option<OutputFormat>,
+  /// Ask before dropping when at a terminal, defaults to true
+  pub confirm: Option<bool>,
+  /// Seconds a whole request may take
+  pub timeout: Option<u64>,
+  /// Seconds connecting to a server may take
+  pub connect_timeout: Option<u64>,
+  /// Times to retry a lookup that failed because of the network or a busy server
+  pub retries: Option<u32>,
+  /// Proxy URL for every request
+  pub proxy: Option<String>,
+  /// PEM bundle to trust instead of the system's CA certificates
+  pub ca_cert: Option<PathBuf>,
+}
+
+impl Config {
+  pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("clink").join("config.toml"))
+  }
+
+  /// Loads the config file at `path`, or the default location if `None`.
+  /// A missing file is just an empty config
+  pub fn load(path: Option<&Path>) -> Result<Config, APIError> {
+    let path = match path.map(Path::to_path_buf).or_else(Config::default_path) {
+      Some(path) => path,
+      None => return Ok(Config::default()),
+    };
+    let contents = match fs::read_to_string(&path) {
+      Ok(contents) => contents,
+      Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+      Err(err) => {
+        return Err(APIError::ConfigError(format!(
+          "Couldn't read {}: {}",
+          path.display(),
+          err
+        )))
+      }
+    };
+    toml::from_str(&contents)
+      .map_err(|err| APIError::ConfigError(format!("Couldn't parse {}: {}", path.display(), err)))
+  }
+
+  /// Layers the profile `name` on top of the top-level settings
+  pub fn resolve(&self, name: &str) -> Result<Config, APIError> {
+    if !name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+      return Err(APIError::ConfigError(format!(
+        "Profile names may only contain letters, numbers, - and _, not \"{}\"",
+        name
+      )));
+    }
+    let profile = match self.profiles.get(name) {
+      Some(profile) => profile.clone(),
+      None if name == DEFAULT_PROFILE => Config::default(),
+      None => {
+        return Err(APIError::ConfigError(format!(
+          "No profile named \"{}\" (try `clink profile list`)",
+          name
+        )))
+      }
+    };
+    if profile.profile.is_some() || !profile.profiles.is_empty() {
+      return Err(APIError::ConfigError(format!(
+        "Profile \"{}\" can't pick or define other profiles",
+        name
+      )));
+    }
+    let base = Config {
+      profile: Some(name.to_string()),
+      profiles: BTreeMap::new(),
+      ..self.clone()
+    };
+    Ok(base.merge(profile))
+  }
+
+  /// Every profile that can be picked, including the default one
+  pub fn profile_names(&self) -> Vec<String> {
+    let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+    if !self.profiles.contains_key(DEFAULT_PROFILE) {
+      names.insert(0, DEFAULT_PROFILE.to_string());
+    }
+    names
+  }
+
+  /// Layers `other` on top of `self`, anything set in `other` wins
+  pub fn merge(self, other: Config) -> Config {
+    Config {
+      profile: other.profile.or(self.profile),
+      profiles: self.profiles,
+      api_url: other.api_url.or(self.api_url),
+      oidc_issuer: other.oidc_issuer.or(self.oidc_issuer),
+      client_id: other.client_id.or(self.client_id),
+      scopes: other.scopes.or(self.scopes),
+      kerberos_realm: other.kerberos_realm.or(self.kerberos_realm),
+      default_ccache: other.default_ccache.or(self.default_ccache),
+      login: other.login.or(self.login),
+      token: other.token.or(self.token),
+      token_file: other.token_file.or(self.token_file),
+      username: other.username.or(self.username),
+      machine: other.machine.or(self.machine),
+      format: other.format.or(self.format),
+      confirm: other.confirm.or(self.confirm),
+      timeout: other.timeout.or(self.timeout),
+      connect_timeout: other.connect_timeout.or(self.connect_timeout),
+      retries: other.retries.or(self.retries),
+      proxy: other.proxy.or(self.proxy),
+      ca_cert: other.ca_cert.or(self.ca_cert),
+    }
+  }
+
+  /// Fills in anything left unset with the CSH defaults
+  pub fn api_config(&self) -> APIConfig {
+    let defaults = APIConfig::default();
+    APIConfig {
+      api_base_url: self.api_url.clone().unwrap_or(defaults.api_base_url),
+      oidc_issuer: self.oidc_issuer.clone().unwrap_or(defaults.oidc_issuer),
+      client_id: self.client_id.clone().unwrap_or(defaults.client_id),
+      scopes: self.scopes.clone().unwrap_or(defaults.scopes),
+      kerberos_realm: self
+        .kerberos_realm
+        .clone()
+        .unwrap_or(defaults.kerberos_realm),
+      kerberos_cache: match self.default_ccache {
+        Some(true) => None,
+        _ => cache::ccache_path(self.profile_name()),
+      },
+      login: self.login.unwrap_or(defaults.login),
+      token: self.token.clone().or(defaults.token),
+      token_file: self.token_file.clone().or(defaults.token_file),
+      username: self.username.clone().or(defaults.username),
+      token_cache: cache::token_path(self.profile_name()),
+      snapshot_cache: cache::snapshot_path(self.profile_name()),
+      history: history::path(self.profile_name()),
+      timeout: self
+        .timeout
+        .map(Duration::from_secs)
+        .unwrap_or(defaults.timeout),
+      connect_timeout: self
+        .connect_timeout
+        .map(Duration::from_secs)
+        .unwrap_or(defaults.connect_timeout),
+      retries: self.retries.unwrap_or(defaults.retries),
+      proxy: self.proxy.clone().or(defaults.proxy),
+      ca_cert: self.ca_cert.clone().or(defaults.ca_cert),
+    }
+  }
+
+  pub fn profile_name(&self) -> &str {
+    self.profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+  }
+
+  pub fn format(&self) -> OutputFormat {
+    self.format.unwrap_or(OutputFormat::Plain)
+  }
+
+  pub fn confirm(&self) -> bool {
+    self.confirm.unwrap_or(true)
+  }
+}
@@ -0,0 +1,116 @@
+use crate::api::{APIError, Item};
+use crate::cache;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// One successful drop
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+  /// Unix timestamp of the drop
+  pub timestamp: u64,
+  pub machine: String,
+  pub slot: u8,
+  /// What was in the slot, if we looked before dropping
+  pub item: Option<String>,
+  pub price: Option<u64>,
+  /// Balance the server reported after the drop
+  pub balance: i64,
+}
+
+impl Entry {
+  pub fn new(machine: String, slot: u8, item: Option<&Item>, balance: i64) -> Entry {
+    Entry {
+      timestamp: cache::now(),
+      machine,
+      slot,
+      item: item.map(|item| item.name.clone()),
+      price: item.map(|item| item.price),
+      balance,
+    }
+  }
+}
+
+/// `$XDG_DATA_HOME/clink/history-<profile>.jsonl`, unlike the cache this is
+/// worth keeping. One per profile, so drops against a dev server don't end up
+/// in the real spending stats
+pub fn path(profile: &str) -> Option<PathBuf> {
+  dirs::data_dir().map(|dir| dir.join("clink").join(format!("history-{}.jsonl", profile)))
+}
+
+/// Adds `entry` to the end of the history at `path`, one JSON object per line
+pub fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+  cache::create_parent(path)?;
+  let mut line = serde_json::to_vec(entry).map_err(io::Error::from)?;
+  line.push(b'\n');
+  let mut file = OpenOptions::new()
+    .append(true)
+    .create(true)
+    .mode(0o600)
+    .open(path)?;
+  // One write per line, so concurrent clinks don't interleave their entries
+  file.write_all(&line)
+}
+
+/// Records a drop in the history at `path`, if there is one. Failing to is
+/// never worth failing the drop over, so this only warns
+pub fn record(path: Option<&Path>, entry: &Entry) {
+  if let Some(path) = path {
+    if let Err(err) = append(path, entry) {
+      eprintln!("Couldn't record drop in {}: {}", path.display(), err);
+    }
+  }
+}
+
+/// Every entry in the history at `path`, oldest first. A missing file is an
+/// empty history, and lines we can't make sense of are skipped
+pub fn read(path: &Path) -> Result<Vec<Entry>, APIError> {
+  let contents = match fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+    Err(err) => {
+      return Err(APIError::HistoryError(format!(
+        "Couldn't read {}: {}",
+        path.display(),
+        err
+      )))
+    }
+  };
+  Ok(
+    contents
+      .lines()
+      .filter_map(|line| serde_json::from_str(line).ok())
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn appends_and_reads_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data").join("history.jsonl");
+    assert_eq!(read(&path).unwrap(), vec![]);
+    let coke = Item {
+      id: 1,
+      name: "Coke".to_string(),
+      price: 50,
+    };
+    let first = Entry::new("bigdrink".to_string(), 1, Some(&coke), 50);
+    let second = Entry::new("bigdrink".to_string(), 2, None, 0);
+    append(&path, &first).unwrap();
+    // A torn or hand-edited line shouldn't lose the rest of the history
+    fs::OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .unwrap()
+      .write_all(b"{\"timestamp\":\n")
+      .unwrap();
+    append(&path, &second).unwrap();
+    assert_eq!(read(&path).unwrap(), vec![first, second]);
+  }
+}
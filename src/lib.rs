@@ -0,0 +1,44 @@
+//! A client for the Computer Science House drink machines.
+//!
+//! [`API`] logs in through CSH SSO (Kerberos, falling back to a password) and
+//! talks to the drink server. It implements [`DrinkBackend`], which is what
+//! the commands and the TUI are written against, so bots can swap in
+//! [`backend::mock::MockBackend`] for tests.
+//!
+//! ```no_run
+//! use clink::{APIConfig, API};
+//!
+//! let api = API::new(APIConfig::default(), Box::new(API::default_password_prompt))?;
+//! for machine in api.get_status_for_machine(None)?.machines {
+//!   println!("{}: {} slots", machine.display_name, machine.slots.len());
+//! }
+//! # Ok::<(), clink::APIError>(())
+//! ```
+//!
+//! The `cli` feature adds the `clink` binary's commands and the `tui` feature
+//! adds the cursive UI. Both are on by default, so headless users should turn
+//! off default features.
+
+pub mod api;
+pub mod auth;
+pub mod backend;
+pub mod cache;
+#[cfg(feature = "cli")]
+pub mod commands;
+pub mod config;
+pub mod history;
+pub mod jwt;
+pub mod oidc;
+pub mod output;
+#[cfg(feature = "cli")]
+pub mod prompt;
+#[cfg(feature = "tui")]
+pub mod ui;
+
+pub use api::{
+  APIConfig, APIError, DrinkList, Item, ItemUpdate, Machine, PasswordFunction, PasswordResult,
+  Slot, SlotUpdate, TryPasswordFn, User, API,
+};
+pub use auth::AuthProvider;
+pub use backend::DrinkBackend;
+pub use oidc::{LoginFlow, LoginNoticeFunction};
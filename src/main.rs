@@ -1,46 +1,313 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use std::path::PathBuf;
 use std::process::ExitCode;
+use std::time::Duration;
 
-pub mod api;
-pub mod commands;
-
-mod ui;
+use clink::{api, cache, commands, config, output};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
   #[clap(subcommand)]
   command: Option<Subcommands>,
-  /// API base URL to use
-  #[clap(value_parser, default_value = "https://drink.csh.rit.edu", long)]
-  api: String,
+  /// Config file to use instead of ~/.config/clink/config.toml
+  #[clap(value_parser, long, env = "CLINK_CONFIG")]
+  config: Option<PathBuf>,
+  /// Profile from the config file to use [default: prod]
+  #[clap(value_parser, long, env = "CLINK_PROFILE")]
+  profile: Option<String>,
+  /// API base URL to use [default: https://drink.csh.rit.edu]
+  #[clap(value_parser, long, env = "CLINK_API_URL")]
+  api: Option<String>,
+  /// OIDC issuer to log in with [default: https://sso.csh.rit.edu/auth/realms/csh]
+  #[clap(value_parser, long, env = "CLINK_OIDC_ISSUER")]
+  oidc_issuer: Option<String>,
+  /// OIDC client id [default: clidrink]
+  #[clap(value_parser, long, env = "CLINK_CLIENT_ID")]
+  client_id: Option<String>,
+  /// Space-separated OIDC scopes [default: openid profile drink_balance]
+  #[clap(value_parser, long, env = "CLINK_SCOPES")]
+  scopes: Option<String>,
+  /// Kerberos realm to log in to [default: CSH.RIT.EDU]
+  #[clap(value_parser, long, env = "CLINK_KERBEROS_REALM")]
+  realm: Option<String>,
+  /// Let kinit write to your default Kerberos credentials cache instead of clink's own
+  #[clap(long, env = "CLINK_DEFAULT_CCACHE")]
+  default_ccache: bool,
+  /// Username to log in as [default: the current user]
+  #[clap(value_parser, long, env = "CLINK_USERNAME")]
+  username: Option<String>,
+  /// How to log in when there's no cached token [default: kerberos]
+  #[clap(value_enum, long, env = "CLINK_LOGIN")]
+  login: Option<clink::LoginFlow>,
+  /// Token to use instead of logging in
+  #[clap(value_parser, long, env = "CLINK_TOKEN", hide_env_values = true)]
+  token: Option<String>,
+  /// File holding a token to use instead of logging in, read whenever one is needed
+  #[clap(value_parser, long, env = "CLINK_TOKEN_FILE")]
+  token_file: Option<PathBuf>,
+  /// Machine to use when a command doesn't name one
+  #[clap(value_parser, long, env = "CLINK_MACHINE")]
+  machine: Option<String>,
+  /// Seconds a whole request may take [default: 30]
+  #[clap(value_parser, long, env = "CLINK_TIMEOUT")]
+  timeout: Option<u64>,
+  /// Seconds connecting to a server may take [default: 10]
+  #[clap(value_parser, long, env = "CLINK_CONNECT_TIMEOUT")]
+  connect_timeout: Option<u64>,
+  /// Times to retry a lookup that failed because of the network or a busy server [default: 3]
+  #[clap(value_parser, long, env = "CLINK_RETRIES")]
+  retries: Option<u32>,
+  /// Proxy to send every request through
+  #[clap(value_parser, long, env = "CLINK_PROXY")]
+  proxy: Option<String>,
+  /// PEM bundle to trust instead of the system's CA certificates
+  #[clap(value_parser, long, env = "CLINK_CA_CERT")]
+  ca_cert: Option<PathBuf>,
+  /// How to print results, ignored by the TUI and `token` [default: plain]
+  #[clap(value_enum, long, global = true, env = "CLINK_FORMAT")]
+  format: Option<output::OutputFormat>,
+}
+
+impl Cli {
+  /// The config layer made up of flags and environment variables
+  fn config(&self) -> config::Config {
+    config::Config {
+      profile: None,
+      profiles: Default::default(),
+      api_url: self.api.clone(),
+      oidc_issuer: self.oidc_issuer.clone(),
+      client_id: self.client_id.clone(),
+      scopes: self.scopes.clone(),
+      kerberos_realm: self.realm.clone(),
+      default_ccache: self.default_ccache.then_some(true),
+      login: self.login,
+      token: self.token.clone(),
+      token_file: self.token_file.clone(),
+      username: self.username.clone(),
+      machine: self.machine.clone(),
+      format: self.format,
+      confirm: None,
+      timeout: self.timeout,
+      connect_timeout: self.connect_timeout,
+      retries: self.retries,
+      proxy: self.proxy.clone(),
+      ca_cert: self.ca_cert.clone(),
+    }
+  }
 }
 
 #[derive(Subcommand)]
 enum Subcommands {
   /// Drops a drink
   Drop {
-    /// Machine to drop from
-    #[clap(value_parser)]
-    machine: String,
+    /// Machine to drop from (may be left out if a default machine is configured)
+    #[clap(value_parser, required_unless_present = "item")]
+    machine: Option<String>,
     /// Slot to drop from
     #[clap(value_parser)]
-    slot: u8,
+    slot: Option<u8>,
+    /// Name of the item to drop (searches every machine instead of using a machine and slot)
+    #[clap(value_parser, long, conflicts_with_all = ["machine", "slot"])]
+    item: Option<String>,
+    /// Skip checking the machine, slot and your balance before dropping
+    #[clap(long)]
+    force: bool,
+    /// Show what would be dropped and what it would cost, without dropping
+    #[clap(long)]
+    dry_run: bool,
+    /// Don't ask for confirmation before dropping
+    #[clap(short, long)]
+    yes: bool,
   },
   /// Lists available drinks
   List {
-    /// Machine whose contents should be shown (if not specified, all will be shown)
+    /// Machine whose contents should be shown (if not specified, the default machine or all will be shown)
     #[clap(value_parser)]
     machine: Option<String>,
+    /// Show every machine, even if a default machine is configured
+    #[clap(long, conflicts_with = "machine")]
+    all: bool,
+    /// Show the last machine list fetched instead of asking the server
+    #[clap(long)]
+    offline: bool,
   },
   /// Prints the number of credits in your account
   Credits,
+  /// Shows who you're logged in as, your balance and when your token expires
+  Whoami,
+  /// Polls for restocks, sell-outs and other changes until stopped
+  Watch {
+    /// Machine to watch (if not specified, the default machine or all will be watched)
+    #[clap(value_parser)]
+    machine: Option<String>,
+    /// Watch every machine, even if a default machine is configured
+    #[clap(long, conflicts_with = "machine")]
+    all: bool,
+    /// Only report changes involving items like this one
+    #[clap(value_parser, long)]
+    item: Option<String>,
+    /// Seconds between polls
+    #[clap(value_parser = clap::value_parser!(u64).range(5..), long, default_value = "30")]
+    interval: u64,
+  },
+  /// Shows drops made from this computer
+  History {
+    /// Only drops on or after this date (YYYY-MM-DD, UTC)
+    #[clap(value_parser = commands::history::parse_date, long)]
+    since: Option<time::Date>,
+    /// Only drops on or before this date (YYYY-MM-DD, UTC)
+    #[clap(value_parser = commands::history::parse_date, long)]
+    until: Option<time::Date>,
+    /// Print totals, the most frequent item and spending per week instead
+    #[clap(value_parser, long)]
+    summary: bool,
+  },
   /// Generates an API token (Plumbing)
   Token,
+  /// Shows the server profiles from the config file
+  Profile {
+    #[clap(subcommand)]
+    command: ProfileCommand,
+  },
+  /// Manages credits, slots and items (drink admins only)
+  Admin {
+    #[clap(subcommand)]
+    command: AdminCommand,
+  },
+  /// Prints a shell completion script, e.g. `clink completions bash > ~/.local/share/bash-completion/completions/clink`
+  Completions {
+    #[clap(value_enum)]
+    shell: clap_complete::Shell,
+  },
+  /// Prints candidates for the word being completed (Plumbing for the completion scripts)
+  #[clap(name = "complete-word", hide = true)]
+  Complete {
+    /// Index into `words` of the word being completed
+    #[clap(long)]
+    index: usize,
+    /// The command line so far, starting with `clink`
+    #[clap(last = true)]
+    words: Vec<String>,
+  },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommand {
+  /// Lists every profile, marking the active one
+  List,
+  /// Shows the settings a profile resolves to
+  Show {
+    /// Profile to show (if not specified, the active one will be shown)
+    #[clap(value_parser)]
+    name: Option<String>,
+  },
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+  /// Looks up or changes someone's drink credits
+  Credits {
+    #[clap(subcommand)]
+    command: CreditsCommand,
+  },
+  /// Changes what's in a slot, whether it's active and how many are left
+  Slot {
+    /// Machine the slot is in
+    #[clap(value_parser, required_unless_present = "from_file")]
+    machine: Option<String>,
+    /// Slot to change
+    #[clap(value_parser, required_unless_present = "from_file")]
+    slot: Option<u8>,
+    /// Item to put in the slot, by id or name
+    #[clap(value_parser, long)]
+    item: Option<String>,
+    /// Whether the slot can be dropped from
+    #[clap(value_parser, long)]
+    active: Option<bool>,
+    /// How many are in the slot
+    #[clap(value_parser, long)]
+    count: Option<u64>,
+    /// Applies every `[[slot]]` in a TOML file instead, e.g. a whole restock
+    #[clap(value_parser, long, conflicts_with_all = ["machine", "slot", "item", "active", "count"])]
+    from_file: Option<PathBuf>,
+  },
+  /// Manages the catalog of items that can go in slots
+  Item {
+    #[clap(subcommand)]
+    command: ItemCommand,
+  },
+}
+
+#[derive(Subcommand)]
+enum ItemCommand {
+  /// Lists every item, stocked or not
+  List,
+  /// Adds a new item
+  Add {
+    /// Name of the item
+    #[clap(value_parser)]
+    name: String,
+    /// Price in credits
+    #[clap(value_parser)]
+    price: u64,
+  },
+  /// Renames or reprices an item
+  Edit {
+    /// Item to change, by id or name
+    #[clap(value_parser)]
+    item: String,
+    /// New name
+    #[clap(value_parser, long, required_unless_present = "price")]
+    name: Option<String>,
+    /// New price in credits
+    #[clap(value_parser, long)]
+    price: Option<u64>,
+    /// Don't ask for confirmation before changing the price
+    #[clap(short, long)]
+    yes: bool,
+  },
+  /// Deletes an item that isn't in any slot
+  Rm {
+    /// Item to delete, by id or name
+    #[clap(value_parser)]
+    item: String,
+    /// Don't ask for confirmation before deleting
+    #[clap(short, long)]
+    yes: bool,
+  },
+}
+
+#[derive(Subcommand)]
+enum CreditsCommand {
+  /// Prints someone's balance
+  Get {
+    /// Username to look up
+    #[clap(value_parser)]
+    uid: String,
+  },
+  /// Sets someone's balance
+  Set {
+    /// Username whose balance to set
+    #[clap(value_parser)]
+    uid: String,
+    /// New balance
+    #[clap(value_parser)]
+    credits: i64,
+  },
+  /// Adds to (or, if negative, takes from) someone's balance
+  Add {
+    /// Username whose balance to change
+    #[clap(value_parser)]
+    uid: String,
+    /// Credits to add
+    #[clap(value_parser, allow_negative_numbers = true)]
+    delta: i64,
+  },
 }
 
-use crate::api::APIError;
 use crate::Subcommands::*;
+use clink::api::APIError;
 
 fn main() -> ExitCode {
   let cli = Cli::parse();
@@ -48,21 +315,198 @@ fn main() -> ExitCode {
   match result {
     Ok(_) => 0,
     Err(APIError::LoginAborted) => 0,
+    // Nothing went wrong, but scripts still need to know nothing was done
+    Err(APIError::Aborted) => APIError::Aborted.exit_code(),
     Err(err) => {
       eprintln!("Error: {}", err);
-      1
+      err.exit_code()
     }
   }
   .into()
 }
 
 fn process_command(cli: Cli) -> Result<(), api::APIError> {
-  let mut api = api::API::new(cli.api, Box::new(api::API::default_password_prompt));
+  // Installing completions shouldn't depend on having a working config
+  if let Some(Completions { shell }) = cli.command {
+    return commands::completions::completions(shell, &mut Cli::command());
+  }
+  let file = config::Config::load(cli.config.as_deref())?;
+  let profile = cli
+    .profile
+    .clone()
+    .or_else(|| file.profile.clone())
+    .unwrap_or_else(|| config::DEFAULT_PROFILE.to_string());
+  let cli_config = cli.config();
+  let config = file.resolve(&profile)?.merge(cli_config.clone());
+  let format = config.format();
+  // Built only by commands that talk to a server, so a bad proxy or CA
+  // bundle can't break the ones that don't
+  let api = || {
+    api::API::new(
+      config.api_config(),
+      Box::new(api::API::default_password_prompt),
+    )
+  };
   match cli.command {
-    Some(Drop { machine, slot }) => commands::drop::drop(&mut api, machine, slot),
-    Some(List { machine }) => commands::list::list(&mut api, machine),
-    Some(Credits) => commands::credits::credits(&mut api),
-    Some(Token) => commands::token::token(&mut api),
-    None => ui::ui_common::launch(api),
+    Some(Drop {
+      item,
+      machine,
+      slot,
+      force,
+      dry_run,
+      yes,
+    }) => {
+      let options = commands::drop::DropOptions {
+        force,
+        dry_run,
+        confirm: config.confirm() && !yes,
+      };
+      match (item, machine, slot) {
+        (Some(item), _, _) => commands::drop::drop_item(&api()?, item, &options, format),
+        (None, Some(machine), Some(slot)) => {
+          commands::drop::drop(&api()?, machine, slot, &options, format)
+        }
+        // `clink drop 3` means slot 3 of the default machine
+        (None, Some(slot), None) => match (config.machine.clone(), slot.parse::<u8>()) {
+          (Some(machine), Ok(slot)) => {
+            commands::drop::drop(&api()?, machine, slot, &options, format)
+          }
+          _ => Cli::command()
+            .error(
+              clap::error::ErrorKind::MissingRequiredArgument,
+              "A slot is required, and a machine too unless a default machine is configured",
+            )
+            .exit(),
+        },
+        // clap won't let us get here without either an item or a machine
+        (None, None, _) => unreachable!(),
+      }
+    }
+    Some(List {
+      machine,
+      all,
+      offline,
+    }) => {
+      let machine = if all {
+        None
+      } else {
+        machine.or(config.machine.clone())
+      };
+      if offline {
+        let snapshot = config
+          .api_config()
+          .snapshot_cache
+          .as_deref()
+          .and_then(cache::read);
+        commands::list::list_offline(snapshot, machine, format)
+      } else {
+        commands::list::list(&api()?, machine, format)
+      }
+    }
+    Some(Credits) => commands::credits::credits(&api()?, format),
+    Some(Whoami) => commands::whoami::whoami(&api()?, format),
+    Some(Watch {
+      machine,
+      all,
+      item,
+      interval,
+    }) => {
+      let machine = if all {
+        None
+      } else {
+        machine.or(config.machine.clone())
+      };
+      commands::watch::watch(
+        &api()?,
+        machine,
+        item,
+        Duration::from_secs(interval),
+        format,
+      )
+    }
+    Some(History {
+      since,
+      until,
+      summary,
+    }) => {
+      let path = config.api_config().history;
+      commands::history::history(path.as_deref(), since, until, summary, format)
+    }
+    Some(Token) => commands::token::token(&api()?),
+    Some(Profile {
+      command: ProfileCommand::List,
+    }) => commands::profile::list(&file, &profile, format),
+    Some(Profile {
+      command: ProfileCommand::Show { name: None },
+    }) => commands::profile::show(&config, format),
+    Some(Profile {
+      command: ProfileCommand::Show { name: Some(name) },
+    }) => commands::profile::show(&file.resolve(&name)?.merge(cli_config), format),
+    Some(Admin {
+      command: AdminCommand::Credits { command },
+    }) => match command {
+      CreditsCommand::Get { uid } => commands::admin::credits::get(&api()?, uid, format),
+      CreditsCommand::Set { uid, credits } => {
+        commands::admin::credits::set(&api()?, uid, credits, format)
+      }
+      CreditsCommand::Add { uid, delta } => {
+        commands::admin::credits::add(&api()?, uid, delta, format)
+      }
+    },
+    Some(Admin {
+      command:
+        AdminCommand::Slot {
+          machine,
+          slot,
+          item,
+          active,
+          count,
+          from_file,
+        },
+    }) => {
+      let changes = match (from_file, machine, slot) {
+        (Some(path), _, _) => commands::admin::slot::load_batch(&path)?,
+        (None, Some(machine), Some(slot)) => vec![commands::admin::slot::SlotChange {
+          machine,
+          slot,
+          item: item.map(commands::admin::slot::ItemRef::Name),
+          active,
+          count,
+        }],
+        // clap won't let us get here without a file or a machine and slot
+        (None, _, _) => unreachable!(),
+      };
+      commands::admin::slot::apply(&api()?, changes, format)
+    }
+    Some(Admin {
+      command: AdminCommand::Item { command },
+    }) => match command {
+      ItemCommand::List => commands::admin::item::list(&api()?, format),
+      ItemCommand::Add { name, price } => commands::admin::item::add(&api()?, name, price, format),
+      ItemCommand::Edit {
+        item,
+        name,
+        price,
+        yes,
+      } => {
+        commands::admin::item::edit(&api()?, item, name, price, config.confirm() && !yes, format)
+      }
+      ItemCommand::Rm { item, yes } => {
+        commands::admin::item::rm(&api()?, item, config.confirm() && !yes, format)
+      }
+    },
+    Some(Completions { .. }) => unreachable!(),
+    Some(Complete { index, words }) => {
+      let mut command = Cli::command();
+      command.build();
+      commands::completions::complete(&command, &file, &profile, index, &words)
+    }
+    #[cfg(feature = "tui")]
+    None => clink::ui::ui_common::launch(api()?),
+    #[cfg(not(feature = "tui"))]
+    None => {
+      Cli::command().print_help().ok();
+      Ok(())
+    }
   }
 }
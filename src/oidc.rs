@@ -0,0 +1,441 @@
+//! Logging in without Kerberos, using the OAuth device authorization grant
+//! or the authorization code flow with PKCE and a localhost redirect
+
+use crate::api::{APIConfig, APIError};
+use crate::cache::{self, CachedToken};
+use crate::jwt;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use isahc::{prelude::*, HttpClient, Request};
+use serde::{de, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+use uuid::Uuid;
+
+/// How long to wait for the browser to come back with a code
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How to get a token from SSO
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum LoginFlow {
+  /// SPNEGO with a Kerberos ticket, running kinit if there isn't one
+  #[default]
+  Kerberos,
+  /// Print a code to enter on another device
+  Device,
+  /// Log in with a browser on this machine
+  Pkce,
+}
+
+/// Shows the user something they need to act on to finish logging in
+pub type LoginNoticeFunction = dyn Fn(String) + Send + 'static;
+
+#[derive(Deserialize, Debug, Clone)]
+struct TokenClaims {
+  exp: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TokenResponse {
+  access_token: String,
+  expires_in: Option<u64>,
+  refresh_token: Option<String>,
+  /// Keycloak says 0 for refresh tokens that don't expire
+  refresh_expires_in: Option<u64>,
+}
+
+impl From<TokenResponse> for CachedToken {
+  fn from(response: TokenResponse) -> CachedToken {
+    CachedToken {
+      refresh_token: response.refresh_token,
+      refresh_expires_at: response
+        .refresh_expires_in
+        .filter(|expires_in| *expires_in > 0)
+        .map(|expires_in| cache::now() + expires_in),
+      ..cached_token(response.access_token, response.expires_in)
+    }
+  }
+}
+
+/// What the token endpoint says when it won't hand out a token (RFC 6749 5.2)
+#[derive(Deserialize, Debug, Clone)]
+struct TokenError {
+  error: String,
+  error_description: Option<String>,
+}
+
+impl From<TokenError> for APIError {
+  fn from(error: TokenError) -> APIError {
+    APIError::SsoFailure(error.error_description.unwrap_or(error.error))
+  }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct DeviceAuthorization {
+  device_code: String,
+  user_code: String,
+  verification_uri: String,
+  verification_uri_complete: Option<String>,
+  expires_in: u64,
+  /// Seconds to wait between polls
+  #[serde(default = "default_interval")]
+  interval: u64,
+}
+
+fn default_interval() -> u64 {
+  5
+}
+
+/// Wraps an access token for the cache, trusting the token's own expiry over
+/// `expires_in` if we can read it
+pub(crate) fn cached_token(access_token: String, expires_in: Option<u64>) -> CachedToken {
+  let expires_at = jwt::decode_claims::<TokenClaims>(&access_token)
+    .ok()
+    .and_then(|claims| claims.exp)
+    .or_else(|| expires_in.map(|expires_in| cache::now() + expires_in));
+  CachedToken {
+    token: format!("Bearer {}", access_token),
+    expires_at,
+    refresh_token: None,
+    refresh_expires_at: None,
+  }
+}
+
+fn endpoint(config: &APIConfig, path: &str) -> String {
+  format!("{}/protocol/openid-connect/{}", config.oidc_issuer, path)
+}
+
+/// POSTs a form to SSO. The inner error is SSO turning us down, which the
+/// device flow expects while the user hasn't finished yet
+fn post_form<T: de::DeserializeOwned>(
+  client: &HttpClient,
+  url: &str,
+  form: &[(&str, &str)],
+) -> Result<Result<T, TokenError>, APIError> {
+  let body = url::form_urlencoded::Serializer::new(String::new())
+    .extend_pairs(form)
+    .finish();
+  let request = Request::post(url)
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .header("Accept", "application/json")
+    .body(body)
+    .map_err(APIError::HTTPError)?;
+  let mut response = client.send(request).map_err(APIError::IsahcError)?;
+  let status = response.status();
+  if status.is_server_error() {
+    return Err(APIError::SsoFailure(format!("{} replied {}", url, status)));
+  }
+  let text = response.text().map_err(|_| APIError::BadFormat)?;
+  match status.is_success() {
+    true => serde_json::from_str(&text)
+      .map(Ok)
+      .map_err(|_| APIError::BadFormat),
+    false => serde_json::from_str(&text)
+      .map(Err)
+      .map_err(|_| APIError::SsoFailure(format!("{} replied {}", url, status))),
+  }
+}
+
+/// RFC 8628: show the user a code to enter elsewhere, then poll until they have
+pub(crate) fn device(
+  client: &HttpClient,
+  config: &APIConfig,
+  notice: &dyn Fn(String),
+) -> Result<CachedToken, APIError> {
+  let authorization: DeviceAuthorization = post_form(
+    client,
+    &endpoint(config, "auth/device"),
+    &[("client_id", &config.client_id), ("scope", &config.scopes)],
+  )??;
+  notice(match &authorization.verification_uri_complete {
+    Some(complete) => format!(
+      "To log in, open {} (or go to {} and enter {})",
+      complete, authorization.verification_uri, authorization.user_code
+    ),
+    None => format!(
+      "To log in, go to {} and enter {}",
+      authorization.verification_uri, authorization.user_code
+    ),
+  });
+  let deadline = Instant::now() + Duration::from_secs(authorization.expires_in);
+  let mut interval = Duration::from_secs(authorization.interval);
+  loop {
+    thread::sleep(interval);
+    if Instant::now() > deadline {
+      return Err(APIError::SsoFailure(
+        "The login code expired before it was used".to_string(),
+      ));
+    }
+    let response = post_form::<TokenResponse>(
+      client,
+      &endpoint(config, "token"),
+      &[
+        ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+        ("device_code", &authorization.device_code),
+        ("client_id", &config.client_id),
+      ],
+    )?;
+    match response {
+      Ok(token) => return Ok(token.into()),
+      Err(error) => match error.error.as_str() {
+        "authorization_pending" => {}
+        "slow_down" => interval += Duration::from_secs(5),
+        _ => return Err(error.into()),
+      },
+    }
+  }
+}
+
+/// Trades `token`'s refresh token for a new access token. `None` means SSO
+/// turned the refresh token down, and the only way forward is logging in again
+pub(crate) fn refresh(
+  client: &HttpClient,
+  config: &APIConfig,
+  token: &CachedToken,
+) -> Result<Option<CachedToken>, APIError> {
+  let refresh_token = match &token.refresh_token {
+    Some(refresh_token) => refresh_token,
+    None => return Ok(None),
+  };
+  let response = post_form::<TokenResponse>(
+    client,
+    &endpoint(config, "token"),
+    &[
+      ("grant_type", "refresh_token"),
+      ("refresh_token", refresh_token),
+      ("client_id", &config.client_id),
+    ],
+  )?;
+  Ok(response.ok().map(|response| {
+    let fresh: CachedToken = response.into();
+    // SSO doesn't have to hand out a new refresh token every time
+    match fresh.refresh_token {
+      Some(_) => fresh,
+      None => CachedToken {
+        refresh_token: token.refresh_token.clone(),
+        refresh_expires_at: token.refresh_expires_at,
+        ..fresh
+      },
+    }
+  }))
+}
+
+/// Enough randomness for a PKCE verifier or a state, as 43 URL-safe characters
+fn random_string() -> String {
+  let bytes: Vec<u8> = [Uuid::new_v4(), Uuid::new_v4()]
+    .iter()
+    .flat_map(|uuid| *uuid.as_bytes())
+    .collect();
+  URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// RFC 7636: send the user's browser to SSO, and catch the code it comes back
+/// with on a listener that only lives as long as the login
+pub(crate) fn pkce(
+  client: &HttpClient,
+  config: &APIConfig,
+  notice: &dyn Fn(String),
+) -> Result<CachedToken, APIError> {
+  let listener = TcpListener::bind(("127.0.0.1", 0)).map_err(|err| {
+    APIError::SsoFailure(format!("Couldn't listen for the login redirect: {}", err))
+  })?;
+  let port = listener.local_addr().map_err(|err| {
+    APIError::SsoFailure(format!("Couldn't listen for the login redirect: {}", err))
+  })?;
+  let redirect_uri = format!("http://127.0.0.1:{}/callback", port.port());
+  let authorization = AuthorizationRequest::new(config, &redirect_uri);
+  notice(format!(
+    "To log in, open {} in a browser",
+    authorization.url
+  ));
+  let code = wait_for_redirect(&listener, &authorization.state)?;
+  authorization.exchange(client, config, &code)
+}
+
+/// An authorization code request with PKCE (RFC 7636), and what we need to
+/// check and redeem the code SSO answers it with
+pub(crate) struct AuthorizationRequest {
+  pub url: String,
+  pub state: String,
+  redirect_uri: String,
+  verifier: String,
+}
+
+impl AuthorizationRequest {
+  pub fn new(config: &APIConfig, redirect_uri: &str) -> AuthorizationRequest {
+    let verifier = random_string();
+    let state = random_string();
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+      .append_pair("client_id", &config.client_id)
+      .append_pair("redirect_uri", redirect_uri)
+      .append_pair("response_type", "code")
+      .append_pair("scope", &config.scopes)
+      .append_pair("state", &state)
+      .append_pair("code_challenge", &challenge)
+      .append_pair("code_challenge_method", "S256")
+      .finish()
+      // Keycloak wants %20 rather than + for spaces, a literal + is already %2B
+      .replace('+', "%20");
+    AuthorizationRequest {
+      url: format!("{}?{}", endpoint(config, "auth"), query),
+      state,
+      redirect_uri: redirect_uri.to_string(),
+      verifier,
+    }
+  }
+
+  /// Trades the code SSO redirected back with for a token
+  pub fn exchange(
+    &self,
+    client: &HttpClient,
+    config: &APIConfig,
+    code: &str,
+  ) -> Result<CachedToken, APIError> {
+    let token: TokenResponse = post_form(
+      client,
+      &endpoint(config, "token"),
+      &[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &self.redirect_uri),
+        ("client_id", &config.client_id),
+        ("code_verifier", &self.verifier),
+      ],
+    )??;
+    Ok(token.into())
+  }
+}
+
+/// An implicit flow request, what clink sent before it used codes. Clients
+/// that are only allowed this flow turn code requests away, and get a token
+/// but no refresh token from this
+pub(crate) struct ImplicitRequest {
+  pub url: String,
+  pub state: String,
+}
+
+impl ImplicitRequest {
+  pub fn new(config: &APIConfig, redirect_uri: &str) -> ImplicitRequest {
+    let state = random_string();
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+      .append_pair("client_id", &config.client_id)
+      .append_pair("redirect_uri", redirect_uri)
+      .append_pair("response_type", "token id_token")
+      .append_pair("scope", &config.scopes)
+      .append_pair("state", &state)
+      .append_pair("nonce", &random_string())
+      .finish()
+      // Keycloak wants %20 rather than + for spaces, a literal + is already %2B
+      .replace('+', "%20");
+    ImplicitRequest {
+      url: format!("{}?{}", endpoint(config, "auth"), query),
+      state,
+    }
+  }
+}
+
+/// Answers requests on `listener` until one is the redirect back from SSO,
+/// returning its code
+fn wait_for_redirect(listener: &TcpListener, state: &str) -> Result<String, APIError> {
+  let deadline = Instant::now() + REDIRECT_TIMEOUT;
+  let failed = |err: std::io::Error| {
+    APIError::SsoFailure(format!("Couldn't listen for the login redirect: {}", err))
+  };
+  listener.set_nonblocking(true).map_err(failed)?;
+  loop {
+    let mut stream = match listener.accept() {
+      Ok((stream, _)) => stream,
+      Err(err) if err.kind() == ErrorKind::WouldBlock => {
+        if Instant::now() > deadline {
+          return Err(APIError::SsoFailure(
+            "Timed out waiting for the browser to log in".to_string(),
+          ));
+        }
+        thread::sleep(Duration::from_millis(100));
+        continue;
+      }
+      Err(err) => return Err(failed(err)),
+    };
+    // Browsers also ask for things like /favicon.ico, and anything on this
+    // machine can connect, so only a matching state ends the wait
+    let url = match read_request_url(&stream) {
+      Some(url) if url.path() == "/callback" => url,
+      _ => {
+        respond(&mut stream, "404 Not Found", "Not found");
+        continue;
+      }
+    };
+    let param = |key: &str| {
+      url
+        .query_pairs()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.to_string())
+    };
+    if param("state").as_deref() != Some(state) {
+      respond(
+        &mut stream,
+        "400 Bad Request",
+        "This isn't the login clink started",
+      );
+      continue;
+    }
+    match (
+      param("code"),
+      param("error_description").or_else(|| param("error")),
+    ) {
+      (Some(code), _) => {
+        respond(&mut stream, "200 OK", "Logged in, you can close this tab");
+        return Ok(code);
+      }
+      (None, error) => {
+        respond(
+          &mut stream,
+          "200 OK",
+          "Couldn't log in, see clink for details",
+        );
+        return Err(APIError::SsoFailure(
+          error.unwrap_or_else(|| "No code in the redirect".to_string()),
+        ));
+      }
+    }
+  }
+}
+
+fn read_request_url(stream: &TcpStream) -> Option<Url> {
+  stream.set_nonblocking(false).ok()?;
+  stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+  let mut request_line = String::new();
+  BufReader::new(stream).read_line(&mut request_line).ok()?;
+  let target = request_line.split_whitespace().nth(1)?;
+  Url::parse(&format!("http://127.0.0.1{}", target)).ok()
+}
+
+fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+  let response = format!(
+    "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    message.len(),
+    message
+  );
+  // The browser going away doesn't change whether we got a code
+  stream.write_all(response.as_bytes()).ok();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn random_strings_are_long_enough_for_pkce() {
+    let verifier = random_string();
+    assert_eq!(verifier.len(), 43);
+    assert_ne!(verifier, random_string());
+  }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+/// A Unix timestamp as `YYYY-MM-DD HH:MM UTC`
+pub fn format_timestamp(timestamp: u64) -> String {
+  // panic rationale: any date in range formats fine
+  OffsetDateTime::from_unix_timestamp(timestamp as i64)
+    .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+    .format(format_description!(
+      "[year]-[month]-[day] [hour]:[minute] UTC"
+    ))
+    .unwrap()
+}
+
+/// How commands print their results
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+  /// Human-readable text
+  Plain,
+  /// A single line of JSON
+  Json,
+  /// Comma-separated values with a header row
+  Csv,
+  /// Tab-separated values with a header row
+  Tsv,
+}
+
+impl OutputFormat {
+  pub fn print_json<T: Serialize>(value: &T) {
+    // panic rationale: everything we print is plain data that always serializes
+    println!("{}", serde_json::to_string(value).unwrap());
+  }
+
+  /// Prints a header row followed by `rows`, only meaningful for Csv and Tsv
+  pub fn print_table(&self, header: &[&str], rows: &[Vec<String>]) {
+    let separator = match self {
+      OutputFormat::Tsv => "\t",
+      _ => ",",
+    };
+    let header: Vec<String> = header.iter().map(|field| field.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+      let fields: Vec<String> = row.iter().map(|field| self.escape(field)).collect();
+      println!("{}", fields.join(separator));
+    }
+  }
+
+  fn escape(&self, field: &str) -> String {
+    match self {
+      OutputFormat::Tsv => field.replace(['\t', '\n', '\r'], " "),
+      _ if field.contains([',', '"', '\n', '\r']) => format!("\"{}\"", field.replace('"', "\"\"")),
+      _ => field.to_string(),
+    }
+  }
+}
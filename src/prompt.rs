@@ -0,0 +1,28 @@
+use crate::api::APIError;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Whether there's someone at a terminal to answer questions
+pub fn interactive() -> bool {
+  io::stdin().is_terminal()
+}
+
+/// Like [`confirm`], but with no one at a terminal to answer, refuses with
+/// `APIError::Unconfirmed` rather than going ahead
+pub fn require_confirmation(question: &str) -> Result<(), APIError> {
+  match interactive() {
+    true => confirm(question),
+    false => Err(APIError::Unconfirmed(question.to_string())),
+  }
+}
+
+/// Asks a yes/no question on stderr, anything but yes is `APIError::Aborted`
+pub fn confirm(question: &str) -> Result<(), APIError> {
+  eprint!("{} [y/N]: ", question);
+  io::stderr().flush().ok();
+  let mut answer = String::new();
+  io::stdin().lock().read_line(&mut answer).ok();
+  match answer.trim().to_lowercase().as_str() {
+    "y" | "yes" => Ok(()),
+    _ => Err(APIError::Aborted),
+  }
+}
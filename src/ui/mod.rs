@@ -1,2 +1,4 @@
+//! The cursive TUI `clink` opens when run without a subcommand
+
 pub mod store;
 pub mod ui_common;
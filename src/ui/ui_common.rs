@@ -1,4 +1,7 @@
-use crate::api::{APIError, DrinkList, Machine, Slot, API};
+use crate::api::{APIError, DrinkList, Machine, Slot};
+use crate::backend::DrinkBackend;
+use crate::cache::Snapshot;
+use crate::history::{self, Entry};
 use crate::ui::store::{ListenerView, Store};
 use cursive;
 use cursive::align::{HAlign, VAlign};
@@ -13,21 +16,28 @@ use cursive::views::{
   ShadowView, TextView,
 };
 use cursive::{Cursive, CursiveRunnable};
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+
+/// How often to try the server again while showing a saved machine list
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
 
 struct ModelData {
   credits: Mutex<Store<Option<i64>>>,
   machines: Mutex<Store<Option<DrinkList>>>,
-  api: API,
+  /// When the machine list we're showing was saved, if we couldn't reach
+  /// the server and are showing it read-only
+  stale_as_of: Mutex<Option<String>>,
+  api: Box<dyn DrinkBackend>,
 }
 
 // This should really get cleaned up:
 type Model = Arc<ModelData>;
 
 /// Entrypoint, CLI will call this when we start up!
-pub fn launch(mut api: API) -> Result<(), APIError> {
+pub fn launch(mut api: impl DrinkBackend + 'static) -> Result<(), APIError> {
   let mut siv = cursive::default();
   let (tx_credential, rx_credential) = channel();
   let tx_credential = Arc::new(Mutex::new(tx_credential));
@@ -46,12 +56,10 @@ pub fn launch(mut api: API) -> Result<(), APIError> {
         match rx_credential.recv() {
           Ok(Some(password)) => {
             let result = (password_cb)(password);
-            let success = result
-              .as_ref()
-              .map(|result| result.success)
-              .unwrap_or(false);
+            // Errors (like kinit missing) won't go away by asking again
+            let done = result.as_ref().map(|result| result.success).unwrap_or(true);
             tx_close.send(result).unwrap();
-            if success {
+            if done {
               break;
             }
           }
@@ -80,14 +88,23 @@ pub fn launch(mut api: API) -> Result<(), APIError> {
                       .unwrap()
                       .send(Some(password.to_string()))
                       .unwrap();
-                    if let Ok(result) = rx_close.lock().unwrap().recv().unwrap() {
-                      if result.success {
+                    match rx_close.lock().unwrap().recv().unwrap() {
+                      Ok(result) if result.success => {
                         siv.pop_layer();
-                      } else {
+                      }
+                      Ok(result) => {
                         siv.call_on_name("password_message", move |view: &mut TextView| {
                           view.set_content(result.message);
                         });
                       }
+                      Err(err) => {
+                        siv.pop_layer();
+                        siv.add_layer(
+                          Dialog::around(TextView::new(err.to_string()))
+                            .title("Couldn't log in")
+                            .button("Quit", |siv| siv.quit()),
+                        );
+                      }
                     }
                   })),
               )
@@ -101,11 +118,28 @@ pub fn launch(mut api: API) -> Result<(), APIError> {
       }
     });
   }
+  {
+    let cb_sink = siv.cb_sink().clone();
+    api.set_login_notice(Box::new(move |message| {
+      cb_sink
+        .send(Box::new(move |siv| {
+          siv.add_layer(
+            Dialog::around(TextView::new(message))
+              .title("Log in")
+              .button("Done", |siv| {
+                siv.pop_layer();
+              }),
+          );
+        }))
+        .ok();
+    }));
+  }
   // api.get_token()?;
   let model = Arc::new(ModelData {
     credits: Mutex::new(Store::new(None)),
     machines: Mutex::new(Store::new(None)),
-    api,
+    stale_as_of: Mutex::new(None),
+    api: Box::new(api),
   });
 
   // Nice to have
@@ -117,11 +151,26 @@ pub fn launch(mut api: API) -> Result<(), APIError> {
 
   machine_list(Arc::clone(&model), &mut siv, padding);
 
+  // Dropped once the UI closes, which stops any reconnect attempts
+  let (tx_quit, rx_quit) = channel::<()>();
   let status_handle = {
     let model = Arc::clone(&model);
     let cb_sink = siv.cb_sink().clone();
     thread::spawn(move || {
-      let machine_list = model.api.get_status_for_machine(None)?;
+      let machine_list = match model.api.get_status_for_machine(None) {
+        Ok(machine_list) => machine_list,
+        Err(err) if err.is_unreachable() => match model.api.cached_status() {
+          Some(snapshot) => {
+            show_stale(&model, &cb_sink, snapshot);
+            match reconnect(&model, &cb_sink, &rx_quit)? {
+              Some(machine_list) => machine_list,
+              None => return Ok(()),
+            }
+          }
+          None => return Err(show_error(&cb_sink, "Couldn't load machines", err)),
+        },
+        Err(err) => return Err(show_error(&cb_sink, "Couldn't load machines", err)),
+      };
       let model = Arc::clone(&model);
       cb_sink
         .send(Box::new(move |siv| {
@@ -137,7 +186,12 @@ pub fn launch(mut api: API) -> Result<(), APIError> {
     let cb_sink = siv.cb_sink().clone();
     thread::spawn(move || {
       let api = &model.api;
-      let credit_count = api.get_credits()?;
+      let credit_count = match api.get_credits() {
+        Ok(credit_count) => credit_count,
+        // Loading the machines says we're offline, and fetches credits once we aren't
+        Err(err) if err.is_unreachable() => return Ok(()),
+        Err(err) => return Err(show_error(&cb_sink, "Couldn't load credits", err)),
+      };
       let model = Arc::clone(&model);
       cb_sink
         .send(Box::new(move |siv| {
@@ -150,12 +204,84 @@ pub fn launch(mut api: API) -> Result<(), APIError> {
 
   siv.run();
 
+  drop(tx_quit);
   tx_credential_clone.lock().unwrap().send(None).unwrap();
   status_handle.join().unwrap()?;
   credits_handle.join().unwrap()?;
   Ok(())
 }
 
+/// Shows a saved machine list read-only, since it may not match the machines
+fn show_stale(model: &Model, cb_sink: &cursive::CbSink, snapshot: Snapshot) {
+  let as_of = snapshot.as_of();
+  *model.stale_as_of.lock().unwrap() = Some(as_of.clone());
+  let model = Arc::clone(model);
+  cb_sink
+    .send(Box::new(move |siv| {
+      model
+        .machines
+        .lock()
+        .unwrap()
+        .set(siv, Some(snapshot.drinks));
+      siv.call_on_all_named("machine_list_dialog", |dialog: &mut Dialog| {
+        dialog.set_title(format!("Offline, as of {}", as_of));
+      });
+    }))
+    .unwrap();
+}
+
+/// Keeps trying the server until it answers, then leaves read-only mode.
+/// `None` means the UI closed first
+fn reconnect(
+  model: &Model,
+  cb_sink: &cursive::CbSink,
+  rx_quit: &Receiver<()>,
+) -> Result<Option<DrinkList>, APIError> {
+  loop {
+    match rx_quit.recv_timeout(RECONNECT_INTERVAL) {
+      Err(RecvTimeoutError::Timeout) => {}
+      _ => return Ok(None),
+    }
+    match model.api.get_status_for_machine(None) {
+      Ok(machine_list) => {
+        *model.stale_as_of.lock().unwrap() = None;
+        let credits = model.api.get_credits().ok();
+        let model = Arc::clone(model);
+        cb_sink
+          .send(Box::new(move |siv| {
+            siv.call_on_all_named("machine_list_dialog", |dialog: &mut Dialog| {
+              dialog.set_title("Select a Machine");
+            });
+            if credits.is_some() {
+              model.credits.lock().unwrap().set(siv, credits);
+            }
+          }))
+          .unwrap();
+        return Ok(Some(machine_list));
+      }
+      Err(err) if err.is_unreachable() => {}
+      Err(err) => return Err(show_error(cb_sink, "Couldn't load machines", err)),
+    }
+  }
+}
+
+/// Tells the user a background load failed, handing back the error so it
+/// can still decide the exit code once they quit
+fn show_error(cb_sink: &cursive::CbSink, title: &'static str, err: APIError) -> APIError {
+  let message = err.to_string();
+  // The UI may already be gone, in which case there's no one to tell
+  cb_sink
+    .send(Box::new(move |siv| {
+      siv.add_layer(
+        Dialog::around(TextView::new(message))
+          .title(title)
+          .button("Quit", |siv| siv.quit()),
+      );
+    }))
+    .ok();
+  err
+}
+
 /// Draws CSH logo in the corner
 fn csh_logo(siv: &mut CursiveRunnable) {
   let logo = TextView::new(SpannedString::styled(
@@ -360,6 +486,20 @@ fn item_list(model: Model, siv: &mut Cursive, machine_id: u64, padding: Margins)
 /// Fires off a drop and shows a message to the user
 /// Pops off when finished
 fn drop_drink(model: Model, siv: &mut Cursive, slot: &Slot) {
+  let stale_as_of = model.stale_as_of.lock().unwrap().clone();
+  if let Some(as_of) = stale_as_of {
+    siv.add_layer(
+      Dialog::around(TextView::new(format!(
+        "Can't reach the drink server, so this is the list as of {}. Drops will work again once clink reconnects",
+        as_of
+      )))
+      .button("Done", |siv| {
+        siv.pop_layer();
+      })
+      .title("Offline"),
+    );
+    return;
+  }
   let machine_id = slot.machine;
   let machine_id = model
     .machines
@@ -378,9 +518,15 @@ fn drop_drink(model: Model, siv: &mut Cursive, slot: &Slot) {
   siv.add_layer(dialog);
   let cb_sink = siv.cb_sink().clone();
   let slot_number = slot.number;
+  let item = slot.item.clone();
   thread::spawn(move || {
-    match model.api.drop(machine_id, slot_number) {
+    match model.api.as_ref().drop(machine_id.clone(), slot_number) {
       Ok(credits) => {
+        // Warning on stderr would scribble over the TUI, so just carry on
+        if let Some(path) = model.api.history_path() {
+          let entry = Entry::new(machine_id, slot_number, Some(&item), credits);
+          history::append(&path, &entry).ok();
+        }
         let message = format!("Enjoy! You now have {} credits", credits);
         let api = &model.api;
         let model = Arc::clone(&model);
@@ -398,16 +544,22 @@ fn drop_drink(model: Model, siv: &mut Cursive, slot: &Slot) {
             );
           }))
           .unwrap();
-        let status = api.get_status_for_machine(None).unwrap();
-        cb_sink
-          .send(Box::new(move |siv| {
-            model_ref.machines.lock().unwrap().set(siv, Some(status));
-          }))
-          .unwrap();
+        // A stale list beats a crash, the drop itself already worked
+        if let Ok(status) = api.get_status_for_machine(None) {
+          cb_sink
+            .send(Box::new(move |siv| {
+              model_ref.machines.lock().unwrap().set(siv, Some(status));
+            }))
+            .unwrap();
+        }
       }
       Err(err) => {
         let message = match err {
-          APIError::ServerError(_path, message) => message,
+          APIError::ServerError(_, message)
+          | APIError::ServerUnavailable(_, message)
+          | APIError::InsufficientCredits(message)
+          | APIError::MachineOffline(message)
+          | APIError::SlotUnavailable(message) => message,
           err => format!("Couldn't drop a drink: {:?}", err),
         };
         cb_sink
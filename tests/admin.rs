@@ -0,0 +1,212 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::{json, Value};
+
+#[test]
+fn looks_up_credits() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let run = Clink::new().run(&stand_in, &["admin", "credits", "get", "someone"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "someone has 20 credits\n");
+}
+
+#[test]
+fn changes_credits() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let clink = Clink::new();
+  let run = clink.run(&stand_in, &["admin", "credits", "add", "someone", "-5"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "Changed someone's credits from 20 to 15 (-5)\n");
+  let run = clink.run(
+    &stand_in,
+    &[
+      "admin", "credits", "set", "someone", "100", "--format", "json",
+    ],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let output: Value = serde_json::from_str(&run.stdout).unwrap();
+  assert_eq!(
+    output,
+    json!({"uid": "someone", "old_credits": 15, "credits": 100})
+  );
+  assert_eq!(stand_in.state.lock().unwrap().users["someone"], 100);
+}
+
+#[test]
+fn non_admins_are_forbidden() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["admin", "credits", "set", "someone", "9000"]);
+  assert_eq!(run.code, 8);
+  assert!(run.stderr.contains("Only drink admins"), "{}", run.stderr);
+  assert_eq!(stand_in.state.lock().unwrap().users["someone"], 20);
+}
+
+#[test]
+fn unknown_users() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let run = Clink::new().run(&stand_in, &["admin", "credits", "get", "nobody"]);
+  assert_eq!(run.code, 7);
+}
+
+#[test]
+fn changes_a_slot() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let run = Clink::new().run(
+    &stand_in,
+    &[
+      "admin", "slot", "bigdrink", "3", "--item", "sprite", "--count", "12",
+    ],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(
+    run.stdout,
+    "bigdrink slot 3:\n  item: Water (3) -> Sprite (5)\n  count: 0 -> 12\n"
+  );
+  let request = &stand_in.requests_to("/drinks/slots/bigdrink/3")[0];
+  let body: Value = serde_json::from_str(&request.body).unwrap();
+  assert_eq!(body, json!({"item_id": 5, "count": 12}));
+}
+
+#[test]
+fn escapes_machine_names() {
+  let stand_in = StandIn::start();
+  {
+    let mut state = stand_in.state.lock().unwrap();
+    state.admin = true;
+    state.drinks["machines"][0]["name"] = json!("big drink");
+  }
+  let path = "/drinks/slots/big%20drink/3";
+  stand_in.respond(
+    path,
+    Response::json(200, json!({"message": "Slot updated"})),
+  );
+  let run = Clink::new().run(
+    &stand_in,
+    &["admin", "slot", "big drink", "3", "--count", "1"],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(stand_in.requests_to(path).len(), 1);
+}
+
+#[test]
+fn applies_a_batch() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let clink = Clink::new();
+  let batch = clink.home.path().join("restock.toml");
+  std::fs::write(
+    &batch,
+    r#"
+    [[slot]]
+    machine = "bigdrink"
+    slot = 1
+    count = 10
+
+    [[slot]]
+    machine = "bigdrink"
+    slot = 2
+    active = false
+    "#,
+  )
+  .unwrap();
+  let run = clink.run(
+    &stand_in,
+    &[
+      "admin",
+      "slot",
+      "--from-file",
+      batch.to_str().unwrap(),
+      "--format",
+      "csv",
+    ],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(
+    run.stdout,
+    "machine,slot,field,before,after\nbigdrink,1,count,5,10\nbigdrink,2,active,true,false\n"
+  );
+}
+
+#[test]
+fn slot_changes_need_admin() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(
+    &stand_in,
+    &["admin", "slot", "bigdrink", "1", "--active", "false"],
+  );
+  assert_eq!(run.code, 8, "{}", run.stderr);
+}
+
+#[test]
+fn manages_items() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let clink = Clink::new();
+  let run = clink.run(&stand_in, &["admin", "item", "add", "Fanta", "45"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "Added Fanta (6) at 45 credits\n");
+  let run = clink.run(
+    &stand_in,
+    &[
+      "admin",
+      "item",
+      "edit",
+      "fanta",
+      "--price",
+      "30",
+      "--name",
+      "Orange Fanta",
+      "--yes",
+    ],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(
+    run.stdout,
+    "Fanta (6): name Fanta -> Orange Fanta, price 45 -> 30 credits\n"
+  );
+  let run = clink.run(&stand_in, &["admin", "item", "rm", "6", "--yes"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let run = clink.run(&stand_in, &["admin", "item", "list", "--format", "csv"]);
+  assert_eq!(
+    run.stdout,
+    "id,name,price\n1,Coke,50\n2,Cherry Coke,50\n3,Water,25\n5,Sprite,40\n"
+  );
+}
+
+#[test]
+fn scripts_need_yes_to_reprice_or_delete() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let clink = Clink::new();
+  let run = clink.run(
+    &stand_in,
+    &["admin", "item", "edit", "sprite", "--price", "1"],
+  );
+  assert_eq!(run.code, 2, "{}", run.stderr);
+  assert!(run.stderr.contains("--yes"), "{}", run.stderr);
+  let run = clink.run(&stand_in, &["admin", "item", "rm", "sprite"]);
+  assert_eq!(run.code, 2, "{}", run.stderr);
+  // Renaming doesn't need confirming
+  let run = clink.run(
+    &stand_in,
+    &["admin", "item", "edit", "sprite", "--name", "Sprite Zero"],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let run = clink.run(&stand_in, &["admin", "item", "list", "--format", "csv"]);
+  assert!(run.stdout.contains("5,Sprite Zero,40\n"), "{}", run.stdout);
+}
+
+#[test]
+fn wont_delete_stocked_items() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().admin = true;
+  let run = Clink::new().run(&stand_in, &["admin", "item", "rm", "Water"]);
+  assert_eq!(run.code, 13, "{}", run.stderr);
+  assert!(run.stderr.contains("Water (3) is still in bigdrink slot 3"));
+  assert_eq!(stand_in.state.lock().unwrap().items.len(), 4);
+}
@@ -0,0 +1,727 @@
+//! A stand-in for the drink server and Keycloak, just enough of both for
+//! clink to run against, plus helpers for running the clink binary
+
+// Not every test file uses every helper
+#![allow(dead_code)]
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+
+pub const REALM: &str = "/auth/realms/csh";
+pub const AUTH_PATH: &str = "/auth/realms/csh/protocol/openid-connect/auth";
+pub const USERINFO_PATH: &str = "/auth/realms/csh/protocol/openid-connect/userinfo";
+pub const DEVICE_PATH: &str = "/auth/realms/csh/protocol/openid-connect/auth/device";
+pub const TOKEN_PATH: &str = "/auth/realms/csh/protocol/openid-connect/token";
+/// The only authorization code the stand-in hands out
+pub const AUTH_CODE: &str = "stand-in-code";
+
+#[derive(Clone, Debug)]
+pub struct Response {
+  pub status: u16,
+  pub headers: Vec<(String, String)>,
+  pub body: String,
+}
+
+impl Response {
+  pub fn json(status: u16, body: Value) -> Response {
+    Response {
+      status,
+      headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+      body: body.to_string(),
+    }
+  }
+
+  pub fn text(status: u16, body: &str) -> Response {
+    Response {
+      status,
+      headers: vec![],
+      body: body.to_string(),
+    }
+  }
+
+  pub fn with_header(mut self, name: &str, value: &str) -> Response {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  pub fn redirect(location: &str) -> Response {
+    Response {
+      status: 302,
+      headers: vec![("Location".to_string(), location.to_string())],
+      body: String::new(),
+    }
+  }
+
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.as_str())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+  pub method: String,
+  pub path: String,
+  pub query: String,
+  pub headers: HashMap<String, String>,
+  pub body: String,
+}
+
+pub struct State {
+  /// The `/drinks` response, machines and all
+  pub drinks: Value,
+  /// Every item, stocked or not
+  pub items: Vec<Value>,
+  pub balance: i64,
+  pub username: String,
+  /// Whether the logged in user is a drink admin
+  pub admin: bool,
+  /// Everyone else's balances
+  pub users: HashMap<String, i64>,
+  /// The access token handed out by the auth endpoint, and the only one accepted
+  pub token: String,
+  /// The refresh token handed out with every access token, and the only one accepted
+  pub refresh_token: String,
+  /// How many more device flow polls get told to keep waiting
+  pub device_pending: u32,
+  /// The PKCE challenge from the last authorization code request
+  pub challenge: Option<String>,
+  /// Turn code requests away like a client only allowed the implicit flow
+  pub implicit_only: bool,
+  /// Canned responses that replace the real handler for a path
+  pub overrides: HashMap<String, Response>,
+  /// Canned responses for a path that are each used once, before any override
+  pub queued: HashMap<String, VecDeque<Response>>,
+  /// Every request received, oldest first
+  pub requests: Vec<Request>,
+}
+
+pub struct StandIn {
+  pub url: String,
+  pub state: Arc<Mutex<State>>,
+}
+
+/// An unsigned JWT that expires an hour from now
+pub fn fake_jwt(username: &str) -> String {
+  jwt(json!({"preferred_username": username}))
+}
+
+/// An unsigned JWT with `claims`, plus `exp` an hour from now and `iat` unless set
+pub fn jwt(claims: Value) -> String {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs();
+  let mut payload = json!({"exp": now + 3600, "iat": now});
+  for (key, value) in claims.as_object().unwrap() {
+    payload[key] = value.clone();
+  }
+  let encode = |value: Value| URL_SAFE_NO_PAD.encode(value.to_string());
+  format!(
+    "{}.{}.signature",
+    encode(json!({"alg": "none", "typ": "JWT"})),
+    encode(payload)
+  )
+}
+
+pub fn sample_drinks() -> Value {
+  let slot = |machine: u64, number: u8, id: u64, name: &str, price: u64, count: u64| {
+    json!({
+      "active": true,
+      "count": count,
+      "empty": count == 0,
+      "item": {"id": id, "name": name, "price": price},
+      "machine": machine,
+      "number": number,
+    })
+  };
+  json!({
+    "machines": [
+      {
+        "display_name": "Big Drink",
+        "id": 1,
+        "is_online": true,
+        "name": "bigdrink",
+        "slots": [
+          slot(1, 1, 1, "Coke", 50, 5),
+          slot(1, 2, 2, "Cherry Coke", 50, 1),
+          slot(1, 3, 3, "Water", 25, 0),
+        ],
+      },
+      {
+        "display_name": "Little Drink",
+        "id": 2,
+        "is_online": false,
+        "name": "littledrink",
+        "slots": [slot(2, 1, 2, "Cherry Coke", 50, 8)],
+      },
+    ],
+    "message": "Successfully retrieved machine contents!",
+  })
+}
+
+/// Everything in `sample_drinks`, plus Sprite, which isn't in any slot
+pub fn sample_items() -> Vec<Value> {
+  vec![
+    json!({"id": 1, "name": "Coke", "price": 50}),
+    json!({"id": 2, "name": "Cherry Coke", "price": 50}),
+    json!({"id": 3, "name": "Water", "price": 25}),
+    json!({"id": 5, "name": "Sprite", "price": 40}),
+  ]
+}
+
+impl StandIn {
+  pub fn start() -> StandIn {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let state = Arc::new(Mutex::new(State {
+      drinks: sample_drinks(),
+      items: sample_items(),
+      balance: 100,
+      username: "tester".to_string(),
+      admin: false,
+      users: HashMap::from([("someone".to_string(), 20)]),
+      token: fake_jwt("tester"),
+      refresh_token: "stand-in-refresh".to_string(),
+      device_pending: 1,
+      challenge: None,
+      implicit_only: false,
+      overrides: HashMap::new(),
+      queued: HashMap::new(),
+      requests: vec![],
+    }));
+    {
+      let state = Arc::clone(&state);
+      thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+          let state = Arc::clone(&state);
+          thread::spawn(move || handle_connection(stream, &state));
+        }
+      });
+    }
+    StandIn { url, state }
+  }
+
+  pub fn issuer(&self) -> String {
+    format!("{}{}", self.url, REALM)
+  }
+
+  /// Answers every request for `path` with `response` from now on
+  pub fn respond(&self, path: &str, response: Response) {
+    self
+      .state
+      .lock()
+      .unwrap()
+      .overrides
+      .insert(path.to_string(), response);
+  }
+
+  /// Answers the next request for `path` with `response`, after anything
+  /// already queued for it
+  pub fn respond_once(&self, path: &str, response: Response) {
+    self
+      .state
+      .lock()
+      .unwrap()
+      .queued
+      .entry(path.to_string())
+      .or_default()
+      .push_back(response);
+  }
+
+  pub fn requests_to(&self, path: &str) -> Vec<Request> {
+    self
+      .state
+      .lock()
+      .unwrap()
+      .requests
+      .iter()
+      .filter(|request| request.path == path)
+      .cloned()
+      .collect()
+  }
+
+  pub fn balance(&self) -> i64 {
+    self.state.lock().unwrap().balance
+  }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<State>) {
+  let mut reader = BufReader::new(stream.try_clone().unwrap());
+  let mut request_line = String::new();
+  if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+    return;
+  }
+  let mut parts = request_line.split_whitespace();
+  let method = parts.next().unwrap_or_default().to_string();
+  let target = parts.next().unwrap_or_default().to_string();
+  let (path, query) = match target.split_once('?') {
+    Some((path, query)) => (path.to_string(), query.to_string()),
+    None => (target, String::new()),
+  };
+  let mut headers = HashMap::new();
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+      break;
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+  }
+  let length: usize = headers
+    .get("content-length")
+    .and_then(|length| length.parse().ok())
+    .unwrap_or(0);
+  let mut body = vec![0; length];
+  reader.read_exact(&mut body).ok();
+  let request = Request {
+    method,
+    path,
+    query,
+    headers,
+    body: String::from_utf8_lossy(&body).to_string(),
+  };
+
+  let response = {
+    let mut state = state.lock().unwrap();
+    state.requests.push(request.clone());
+    let queued = state
+      .queued
+      .get_mut(&request.path)
+      .and_then(VecDeque::pop_front);
+    match queued.or_else(|| state.overrides.get(&request.path).cloned()) {
+      Some(response) => response,
+      None => route(&mut state, &request),
+    }
+  };
+  write_response(stream, response);
+}
+
+fn write_response(mut stream: TcpStream, response: Response) {
+  let mut head = format!(
+    "HTTP/1.1 {} Stand-In\r\nContent-Length: {}\r\nConnection: close\r\n",
+    response.status,
+    response.body.len()
+  );
+  for (name, value) in &response.headers {
+    head.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  head.push_str("\r\n");
+  stream.write_all(head.as_bytes()).ok();
+  stream.write_all(response.body.as_bytes()).ok();
+}
+
+pub fn query_param(query: &str, key: &str) -> Option<String> {
+  url::form_urlencoded::parse(query.as_bytes())
+    .find(|(name, _)| name == key)
+    .map(|(_, value)| value.to_string())
+}
+
+fn route(state: &mut State, request: &Request) -> Response {
+  if request.path == AUTH_PATH
+    && query_param(&request.query, "response_type").as_deref() == Some("code")
+    && state.implicit_only
+  {
+    return Response::redirect(
+      "drink://callback?error=unauthorized_client&error_description=Client+is+not+allowed+to+initiate+browser+login+with+given+response_type",
+    );
+  }
+  if request.path == AUTH_PATH
+    && query_param(&request.query, "response_type").as_deref() == Some("code")
+  {
+    state.challenge = query_param(&request.query, "code_challenge");
+    let redirect_uri = query_param(&request.query, "redirect_uri").unwrap_or_default();
+    let login_state = query_param(&request.query, "state").unwrap_or_default();
+    return Response::redirect(&format!(
+      "{}?state={}&code={}",
+      redirect_uri, login_state, AUTH_CODE
+    ));
+  }
+  if request.path == AUTH_PATH {
+    return Response::redirect(&format!(
+      "drink://callback#state={}&session_state=stand-in&access_token={}&token_type=bearer&expires_in=3600",
+      query_param(&request.query, "state").unwrap_or_default(),
+      state.token
+    ));
+  }
+  if request.method == "POST" && request.path == DEVICE_PATH {
+    return Response::json(
+      200,
+      json!({
+        "device_code": "stand-in-device",
+        "user_code": "WDJB-MJHT",
+        "verification_uri": "https://sso.example.com/device",
+        "expires_in": 600,
+        "interval": 0,
+      }),
+    );
+  }
+  if request.method == "POST" && request.path == TOKEN_PATH {
+    return token(state, request);
+  }
+  let authorized = request.headers.get("authorization") == Some(&format!("Bearer {}", state.token));
+  if !authorized {
+    return Response::json(401, json!({"error": "Unauthorized"}));
+  }
+  match (request.method.as_str(), request.path.as_str()) {
+    ("GET", USERINFO_PATH) => Response::json(
+      200,
+      json!({
+        "sub": "1234",
+        "preferred_username": state.username,
+        "name": "Test User",
+        "email": format!("{}@csh.rit.edu", state.username),
+        "groups": ["member", "drink"],
+      }),
+    ),
+    ("GET", "/drinks") => {
+      let mut drinks = state.drinks.clone();
+      if let Some(machine) = query_param(&request.query, "machine") {
+        let machines = drinks["machines"].as_array_mut().unwrap();
+        machines.retain(|candidate| candidate["name"] == machine.as_str());
+      }
+      Response::json(200, drinks)
+    }
+    ("GET", "/users/credits") => {
+      let uid = query_param(&request.query, "uid").unwrap_or_default();
+      let balance = match uid == state.username {
+        true => Some(state.balance),
+        false if !state.admin => return forbidden(),
+        false => state.users.get(&uid).copied(),
+      };
+      match balance {
+        Some(balance) => Response::json(
+          200,
+          // The real server sends the balance as a string
+          json!({"message": "Retrieved user credits", "user": {"uid": uid, "drinkBalance": balance.to_string()}}),
+        ),
+        None => Response::json(404, json!({"error": "No such user"})),
+      }
+    }
+    ("PUT", "/users/credits") => {
+      if !state.admin {
+        return forbidden();
+      }
+      let body: Value = serde_json::from_str(&request.body).unwrap_or_default();
+      let (uid, balance) = match (body["uid"].as_str(), body["drinkBalance"].as_i64()) {
+        (Some(uid), Some(balance)) => (uid.to_string(), balance),
+        _ => return Response::json(400, json!({"error": "Bad request"})),
+      };
+      if uid == state.username {
+        state.balance = balance;
+      } else if let Some(credits) = state.users.get_mut(&uid) {
+        *credits = balance;
+      } else {
+        return Response::json(404, json!({"error": "No such user"}));
+      }
+      Response::json(200, json!({"message": "Credits updated"}))
+    }
+    ("POST", "/drinks/drop") => drop(state, request),
+    ("GET", "/drinks/items") => Response::json(
+      200,
+      json!({"message": "Retrieved items", "items": state.items}),
+    ),
+    ("PUT", path) if path.starts_with("/drinks/slots/") => update_slot(state, request),
+    ("POST", "/drinks/items") | ("PUT", "/drinks/items") => save_item(state, request),
+    ("DELETE", path) if path.starts_with("/drinks/items/") => {
+      if !state.admin {
+        return forbidden();
+      }
+      let id = path
+        .trim_start_matches("/drinks/items/")
+        .parse::<u64>()
+        .ok();
+      let before = state.items.len();
+      state.items.retain(|item| item["id"].as_u64() != id);
+      match state.items.len() < before {
+        true => Response::json(200, json!({"message": "Item deleted"})),
+        false => Response::json(400, json!({"error": "Invalid item"})),
+      }
+    }
+    _ => Response::json(404, json!({"error": "Not found"})),
+  }
+}
+
+/// The token endpoint, for the device and authorization code flows
+fn token(state: &mut State, request: &Request) -> Response {
+  let form = |key| query_param(&request.body, key);
+  let refuse = |error: &str| Response::json(400, json!({"error": error}));
+  match form("grant_type").as_deref() {
+    Some("urn:ietf:params:oauth:grant-type:device_code") => {
+      if form("device_code").as_deref() != Some("stand-in-device") {
+        return refuse("invalid_grant");
+      }
+      if state.device_pending > 0 {
+        state.device_pending -= 1;
+        return refuse("authorization_pending");
+      }
+    }
+    Some("authorization_code") => {
+      let challenge = form("code_verifier")
+        .map(|verifier| URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())));
+      if form("code").as_deref() != Some(AUTH_CODE) || challenge != state.challenge {
+        return refuse("invalid_grant");
+      }
+    }
+    Some("refresh_token") => {
+      if form("refresh_token") != Some(state.refresh_token.clone()) {
+        return refuse("invalid_grant");
+      }
+    }
+    _ => return refuse("unsupported_grant_type"),
+  }
+  Response::json(
+    200,
+    json!({
+      "access_token": state.token,
+      "token_type": "Bearer",
+      "expires_in": 3600,
+      "refresh_token": state.refresh_token,
+      "refresh_expires_in": 1800,
+    }),
+  )
+}
+
+/// A bare-bones GET that doesn't follow redirects, for playing the browser
+pub fn get(url: &str) -> Response {
+  let url = url::Url::parse(url).unwrap();
+  let mut stream = TcpStream::connect((url.host_str().unwrap(), url.port().unwrap())).unwrap();
+  let target = match url.query() {
+    Some(query) => format!("{}?{}", url.path(), query),
+    None => url.path().to_string(),
+  };
+  write!(
+    stream,
+    "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+    target,
+    url.host_str().unwrap()
+  )
+  .unwrap();
+  let mut response = String::new();
+  stream.read_to_string(&mut response).unwrap();
+  let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+  let mut lines = head.lines();
+  let status = lines
+    .next()
+    .and_then(|line| line.split_whitespace().nth(1))
+    .and_then(|status| status.parse().ok())
+    .unwrap_or(0);
+  let headers = lines
+    .filter_map(|line| line.split_once(':'))
+    .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+    .collect();
+  Response {
+    status,
+    headers,
+    body: body.to_string(),
+  }
+}
+
+fn forbidden() -> Response {
+  Response::json(403, json!({"error": "Must be a drink admin"}))
+}
+
+/// Creates an item on POST, updates one on PUT
+fn save_item(state: &mut State, request: &Request) -> Response {
+  if !state.admin {
+    return forbidden();
+  }
+  let body: Value = serde_json::from_str(&request.body).unwrap_or_default();
+  if request.method == "POST" {
+    let id = state
+      .items
+      .iter()
+      .filter_map(|item| item["id"].as_u64())
+      .max()
+      .unwrap_or(0)
+      + 1;
+    let item = json!({"id": id, "name": body["name"], "price": body["price"]});
+    state.items.push(item.clone());
+    return Response::json(200, json!({"message": "Item added", "item": item}));
+  }
+  let item = match state.items.iter_mut().find(|item| item["id"] == body["id"]) {
+    Some(item) => item,
+    None => return Response::json(400, json!({"error": "Invalid item"})),
+  };
+  for field in ["name", "price"] {
+    if let Some(value) = body.get(field) {
+      item[field] = value.clone();
+    }
+  }
+  Response::json(200, json!({"message": "Item updated"}))
+}
+
+fn update_slot(state: &mut State, request: &Request) -> Response {
+  if !state.admin {
+    return forbidden();
+  }
+  let parts: Vec<&str> = request.path.split('/').collect();
+  let (machine, number) = match parts.as_slice() {
+    ["", "drinks", "slots", machine, number] => (*machine, number.parse::<u64>().ok()),
+    _ => return Response::json(404, json!({"error": "Not found"})),
+  };
+  let body: Value = serde_json::from_str(&request.body).unwrap_or_default();
+  let item = match body.get("item_id") {
+    Some(id) => match state.items.iter().find(|item| &item["id"] == id) {
+      Some(item) => Some(item.clone()),
+      None => return Response::json(400, json!({"error": "Invalid item"})),
+    },
+    None => None,
+  };
+  let slot = state.drinks["machines"]
+    .as_array_mut()
+    .unwrap()
+    .iter_mut()
+    .filter(|candidate| candidate["name"] == machine)
+    .flat_map(|candidate| candidate["slots"].as_array_mut().unwrap())
+    .find(|slot| slot["number"].as_u64() == number);
+  let slot = match slot {
+    Some(slot) => slot,
+    None => return Response::json(400, json!({"error": "Invalid slot"})),
+  };
+  if let Some(item) = item {
+    slot["item"] = item;
+  }
+  if let Some(active) = body.get("active") {
+    slot["active"] = active.clone();
+  }
+  if let Some(count) = body.get("count") {
+    slot["count"] = count.clone();
+    slot["empty"] = json!(count == 0);
+  }
+  Response::json(200, json!({"message": "Slot updated"}))
+}
+
+fn drop(state: &mut State, request: &Request) -> Response {
+  let body: Value = match serde_json::from_str(&request.body) {
+    Ok(body) => body,
+    Err(_) => return Response::json(400, json!({"error": "Bad request"})),
+  };
+  let balance = state.balance;
+  let machine = state.drinks["machines"]
+    .as_array_mut()
+    .unwrap()
+    .iter_mut()
+    .find(|machine| machine["name"] == body["machine"]);
+  let machine = match machine {
+    Some(machine) => machine,
+    None => return Response::json(400, json!({"error": "Invalid machine name"})),
+  };
+  if machine["is_online"] != true {
+    return Response::json(
+      400,
+      json!({"error": "Could not contact drink machine for drop!"}),
+    );
+  }
+  let slot = machine["slots"]
+    .as_array_mut()
+    .unwrap()
+    .iter_mut()
+    .find(|slot| slot["number"] == body["slot"]);
+  let slot = match slot {
+    Some(slot) => slot,
+    None => return Response::json(400, json!({"error": "Invalid slot"})),
+  };
+  if slot["empty"] == true {
+    return Response::json(400, json!({"error": "The requested slot is empty!"}));
+  }
+  let price = slot["item"]["price"].as_i64().unwrap();
+  if balance < price {
+    return Response::json(
+      402,
+      json!({"error": "The user does not have sufficient drink credits to purchase this item."}),
+    );
+  }
+  let count = slot["count"].as_u64().unwrap_or(1) - 1;
+  slot["count"] = json!(count);
+  slot["empty"] = json!(count == 0);
+  state.balance -= price;
+  Response::json(
+    200,
+    json!({"message": "Drop successful!", "drinkBalance": state.balance}),
+  )
+}
+
+/// What a finished clink run left behind
+pub struct Run {
+  pub code: i32,
+  pub stdout: String,
+  pub stderr: String,
+}
+
+impl From<Output> for Run {
+  fn from(output: Output) -> Run {
+    Run {
+      code: output.status.code().unwrap_or(-1),
+      stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+      stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    }
+  }
+}
+
+/// Runs clink with its own home, config and cache, so tests can't see each
+/// other's tokens or the real user's settings
+pub struct Clink {
+  pub home: TempDir,
+}
+
+impl Clink {
+  pub fn new() -> Clink {
+    Clink {
+      home: tempfile::tempdir().unwrap(),
+    }
+  }
+
+  pub fn command(&self) -> Command {
+    let home = self.home.path();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_clink"));
+    for (key, _) in std::env::vars() {
+      if key.starts_with("CLINK_") || key.to_lowercase().ends_with("_proxy") {
+        command.env_remove(key);
+      }
+    }
+    command
+      .env("HOME", home)
+      .env("XDG_CONFIG_HOME", home.join("config"))
+      .env("XDG_CACHE_HOME", home.join("cache"))
+      .env("XDG_DATA_HOME", home.join("data"))
+      // Keep kinit and klist away from the real user's tickets
+      .env(
+        "KRB5CCNAME",
+        format!("FILE:{}", home.join("krb5cc").display()),
+      )
+      .stdin(Stdio::null());
+    command
+  }
+
+  /// Where clink caches its token for the default profile
+  pub fn token_cache(&self) -> std::path::PathBuf {
+    self.home.path().join("cache/clink/token-prod.json")
+  }
+
+  /// Runs clink against `stand_in` with `args`
+  pub fn run(&self, stand_in: &StandIn, args: &[&str]) -> Run {
+    self
+      .command()
+      .args(["--api", &stand_in.url, "--oidc-issuer", &stand_in.issuer()])
+      .args(["--username", "tester"])
+      .args(args)
+      .output()
+      .unwrap()
+      .into()
+  }
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{Clink, StandIn};
+
+fn complete(clink: &Clink, stand_in: &StandIn, words: &[&str]) -> common::Run {
+  let index = (words.len() - 1).to_string();
+  let mut args = vec!["complete-word", "--index", &index, "--"];
+  args.extend(words);
+  clink.run(stand_in, &args)
+}
+
+#[test]
+fn survives_an_index_past_the_end() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(
+    &stand_in,
+    &["complete-word", "--index", "5", "--", "clink", "--profile"],
+  );
+  // Nothing to offer, rather than a panic
+  assert_eq!(run.code, 7, "{}", run.stderr);
+}
+
+#[test]
+fn completes_from_the_last_listing() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  // Nothing's been fetched yet, so there's nothing to offer
+  let run = complete(&clink, &stand_in, &["clink", "drop", ""]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "");
+
+  assert_eq!(clink.run(&stand_in, &["list"]).code, 0);
+  let requests = stand_in.requests_to("/drinks").len();
+  let run = complete(&clink, &stand_in, &["clink", "drop", ""]);
+  assert_eq!(
+    run.stdout,
+    "bigdrink\tBig Drink\nlittledrink\tLittle Drink (offline)\n"
+  );
+  let run = complete(&clink, &stand_in, &["clink", "drop", "bigdrink", "3"]);
+  assert_eq!(
+    run.stdout,
+    "1\tCoke, 50 credits\n2\tCherry Coke, 50 credits\n3\tWater, 25 credits (empty)\n"
+  );
+  let run = complete(&clink, &stand_in, &["clink", "drop", "--item", "Ch"]);
+  assert_eq!(
+    run.stdout,
+    "Cherry Coke\t50 credits\nCoke\t50 credits\nWater\t25 credits\n"
+  );
+  // Completing never goes back to the server
+  assert_eq!(stand_in.requests_to("/drinks").len(), requests);
+}
+
+#[test]
+fn falls_back_to_static_completions() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  for words in [
+    &["clink", ""][..],
+    &["clink", "credits", ""],
+    &["clink", "drop", "--"],
+    &["clink", "--format", ""],
+  ] {
+    let run = complete(&clink, &stand_in, words);
+    assert_ne!(run.code, 0, "{:?} completed to {}", words, run.stdout);
+  }
+}
+
+#[test]
+fn completes_profiles() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let run = complete(&clink, &stand_in, &["clink", "--profile", ""]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "prod\n");
+}
+
+#[test]
+fn generates_scripts() {
+  let clink = Clink::new();
+  for shell in ["bash", "zsh", "fish"] {
+    let output = clink
+      .command()
+      .args(["completions", shell])
+      .output()
+      .unwrap();
+    assert!(output.status.success());
+    let script = String::from_utf8(output.stdout).unwrap();
+    assert!(script.contains("clink complete-word"), "{}", shell);
+  }
+}
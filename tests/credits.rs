@@ -0,0 +1,57 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::json;
+
+#[test]
+fn prints_credits() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["credits"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "100 credits\n");
+  let request = &stand_in.requests_to("/users/credits")[0];
+  assert_eq!(request.query, "uid=tester");
+}
+
+#[test]
+fn prints_credits_as_json() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["credits", "--format", "json"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "{\"credits\":100}\n");
+}
+
+#[test]
+fn unauthorized() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    common::USERINFO_PATH,
+    Response::json(401, json!({"error": "invalid_token"})),
+  );
+  let run = Clink::new().run(&stand_in, &["credits"]);
+  assert_eq!(run.code, 3, "{}", run.stderr);
+}
+
+#[test]
+fn server_error() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    "/users/credits",
+    Response::json(500, json!({"message": "LDAP is down"})),
+  );
+  let run = Clink::new().run(&stand_in, &["credits"]);
+  assert_eq!(run.code, 1);
+  assert!(run.stderr.contains("LDAP is down"), "{}", run.stderr);
+}
+
+#[test]
+fn malformed_json() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    "/users/credits",
+    Response::json(200, json!({"user": {"drinkBalance": "lots"}})),
+  );
+  let run = Clink::new().run(&stand_in, &["credits"]);
+  assert_eq!(run.code, 1);
+  assert!(run.stderr.contains("BadFormat"), "{}", run.stderr);
+}
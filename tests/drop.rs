@@ -0,0 +1,118 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::{json, Value};
+
+#[test]
+fn drops_a_drink() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "1"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "Item dropped! Your new balance is 50\n");
+  assert_eq!(stand_in.balance(), 50);
+  let request = &stand_in.requests_to("/drinks/drop")[0];
+  let body: Value = serde_json::from_str(&request.body).unwrap();
+  assert_eq!(body, json!({"machine": "bigdrink", "slot": 1}));
+}
+
+#[test]
+fn drops_by_item_name() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(
+    &stand_in,
+    &["drop", "--item", "cherry coke", "--format", "json"],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let output: Value = serde_json::from_str(&run.stdout).unwrap();
+  assert_eq!(
+    output,
+    json!({"machine": "bigdrink", "slot": 2, "credits": 50})
+  );
+}
+
+#[test]
+fn picking_nothing_isnt_success() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().drinks["machines"][1]["is_online"] = json!(true);
+  let run = Clink::new().run(&stand_in, &["drop", "--item", "cherry coke"]);
+  assert_eq!(run.code, 15, "{}", run.stderr);
+  assert!(run.stderr.contains("Several slots match"));
+  assert!(stand_in.requests_to("/drinks/drop").is_empty());
+}
+
+#[test]
+fn dry_run_doesnt_drop() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "2", "--dry-run"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stdout.contains("Would drop Cherry Coke"));
+  assert!(run.stdout.contains("from 100 to 50"));
+  assert!(stand_in.requests_to("/drinks/drop").is_empty());
+}
+
+#[test]
+fn refuses_empty_slots() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "3"]);
+  assert_eq!(run.code, 6, "{}", run.stderr);
+  assert!(stand_in.requests_to("/drinks/drop").is_empty());
+}
+
+#[test]
+fn refuses_offline_machines() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["drop", "littledrink", "1"]);
+  assert_eq!(run.code, 5, "{}", run.stderr);
+}
+
+#[test]
+fn force_skips_preflight() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "3", "--force"]);
+  // The server still says no, but we asked it
+  assert_eq!(run.code, 6, "{}", run.stderr);
+  assert!(run.stderr.contains("The requested slot is empty!"));
+  assert_eq!(stand_in.requests_to("/drinks/drop").len(), 1);
+}
+
+#[test]
+fn insufficient_credits() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().balance = 10;
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "1"]);
+  assert_eq!(run.code, 4, "{}", run.stderr);
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "1", "--force"]);
+  assert_eq!(run.code, 4, "{}", run.stderr);
+}
+
+#[test]
+fn unauthorized() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    "/drinks/drop",
+    Response::json(401, json!({"error": "Unauthorized"})),
+  );
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "1"]);
+  assert_eq!(run.code, 3, "{}", run.stderr);
+}
+
+#[test]
+fn server_error() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    "/drinks/drop",
+    Response::json(500, json!({"error": "Motor jammed"})),
+  );
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "1"]);
+  assert_eq!(run.code, 1);
+  assert!(run.stderr.contains("Motor jammed"), "{}", run.stderr);
+}
+
+#[test]
+fn malformed_json() {
+  let stand_in = StandIn::start();
+  stand_in.respond("/drinks/drop", Response::text(200, "Dropped!"));
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "1"]);
+  assert_eq!(run.code, 1);
+  assert!(run.stderr.contains("BadFormat"), "{}", run.stderr);
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::{json, Value};
+
+#[test]
+fn records_drops() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  for slot in ["1", "2"] {
+    let run = clink.run(&stand_in, &["drop", "bigdrink", slot]);
+    assert_eq!(run.code, 0, "{}", run.stderr);
+  }
+  let run = clink.run(&stand_in, &["history", "--format", "json"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let mut history: Value = serde_json::from_str(&run.stdout).unwrap();
+  for entry in history.as_array_mut().unwrap() {
+    assert!(entry["timestamp"].as_u64().unwrap() > 0);
+    entry.as_object_mut().unwrap().remove("timestamp");
+  }
+  assert_eq!(
+    history,
+    json!([
+      {"machine": "bigdrink", "slot": 1, "item": "Coke", "price": 50, "balance": 50},
+      {"machine": "bigdrink", "slot": 2, "item": "Cherry Coke", "price": 50, "balance": 0},
+    ])
+  );
+
+  let run = clink.run(&stand_in, &["history", "--summary"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stdout.starts_with("2 drops, 100 credits spent\n"));
+}
+
+#[test]
+fn failed_drops_arent_recorded() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  stand_in.respond(
+    "/drinks/drop",
+    Response::json(500, json!({"error": "Machine exploded"})),
+  );
+  let run = clink.run(&stand_in, &["drop", "bigdrink", "1"]);
+  assert_eq!(run.code, 1);
+  let run = clink.run(&stand_in, &["history"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "No drops recorded\n");
+}
+
+#[test]
+fn rejects_bad_dates() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["history", "--since", "yesterday"]);
+  assert_eq!(run.code, 2);
+  assert!(run.stderr.contains("isn't a YYYY-MM-DD date"));
+}
+
+#[test]
+fn keeps_profiles_apart() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let dir = clink.home.path().join("config").join("clink");
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("config.toml"), "[profiles.dev]\n").unwrap();
+  let run = clink.run(&stand_in, &["--profile", "dev", "drop", "bigdrink", "1"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let run = clink.run(&stand_in, &["history"]);
+  assert_eq!(run.stdout, "No drops recorded\n");
+  let run = clink.run(&stand_in, &["--profile", "dev", "history", "--summary"]);
+  assert!(
+    run.stdout.starts_with("1 drops, 50 credits spent\n"),
+    "{}",
+    run.stdout
+  );
+}
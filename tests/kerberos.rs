@@ -0,0 +1,86 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+/// Puts a kinit on clink's PATH that succeeds and logs its `KRB5CCNAME` and
+/// arguments. Returns where the log goes
+fn fake_kinit(clink: &Clink) -> PathBuf {
+  let bin = clink.home.path().join("bin");
+  fs::create_dir_all(&bin).unwrap();
+  let script = bin.join("kinit");
+  fs::write(
+    &script,
+    "#!/bin/sh\necho \"$KRB5CCNAME $*\" >> \"$(dirname \"$0\")/kinit.log\"\n",
+  )
+  .unwrap();
+  fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+  bin.join("kinit.log")
+}
+
+fn run(clink: &Clink, stand_in: &StandIn, args: &[&str]) -> common::Run {
+  let path = format!(
+    "{}:{}",
+    clink.home.path().join("bin").display(),
+    std::env::var("PATH").unwrap_or_default()
+  );
+  clink
+    .command()
+    .env("PATH", path)
+    .args(["--api", &stand_in.url, "--oidc-issuer", &stand_in.issuer()])
+    .args(["--username", "tester"])
+    .args(args)
+    .output()
+    .unwrap()
+    .into()
+}
+
+#[test]
+fn renews_clinks_own_ticket() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let log = fake_kinit(&clink);
+  // Neither the user's ticket nor clink's expired one gets us in
+  stand_in.respond_once(common::AUTH_PATH, Response::text(401, "Unauthorized"));
+  stand_in.respond_once(common::AUTH_PATH, Response::text(401, "Unauthorized"));
+
+  let run = run(&clink, &stand_in, &["token"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let ccache = clink.home.path().join("cache/clink/krb5cc-prod");
+  assert_eq!(
+    fs::read_to_string(log).unwrap(),
+    format!("FILE:{} -R\n", ccache.display())
+  );
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 3);
+}
+
+#[test]
+fn falls_back_to_clinks_own_ticket() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let log = fake_kinit(&clink);
+  // The user's ticket is for some other realm
+  stand_in.respond_once(common::AUTH_PATH, Response::text(401, "Unauthorized"));
+
+  let run = run(&clink, &stand_in, &["token"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(!log.exists());
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 2);
+}
+
+#[test]
+fn renews_in_the_default_cache_when_asked() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let log = fake_kinit(&clink);
+  stand_in.respond_once(common::AUTH_PATH, Response::text(401, "Unauthorized"));
+
+  let run = run(&clink, &stand_in, &["--default-ccache", "token"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(
+    fs::read_to_string(log).unwrap(),
+    format!("FILE:{} -R\n", clink.home.path().join("krb5cc").display())
+  );
+}
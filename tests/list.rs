@@ -0,0 +1,156 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::{json, Value};
+
+#[test]
+fn lists_every_machine() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["list"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stdout.contains("Big Drink (bigdrink)"));
+  assert!(run.stdout.contains("2. Cherry Coke (50 Credits)"));
+  assert!(run.stdout.contains("3. Water (25 Credits) [EMPTY]"));
+  assert!(run.stdout.contains("Little Drink (littledrink)"));
+}
+
+#[test]
+fn lists_one_machine() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["list", "littledrink"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(!run.stdout.contains("Big Drink"));
+  assert!(run.stdout.contains("Little Drink"));
+  let request = &stand_in.requests_to("/drinks")[0];
+  assert_eq!(request.query, "machine=littledrink");
+}
+
+#[test]
+fn lists_every_machine_despite_a_default() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let run = clink.run(&stand_in, &["--machine", "littledrink", "list"]);
+  assert!(!run.stdout.contains("Big Drink"));
+  let run = clink.run(&stand_in, &["--machine", "littledrink", "list", "--all"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stdout.contains("Big Drink"));
+  assert!(run.stdout.contains("Little Drink"));
+  assert_eq!(stand_in.requests_to("/drinks")[1].query, "");
+}
+
+#[test]
+fn lists_as_json() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["list", "--format", "json"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let output: Value = serde_json::from_str(&run.stdout).unwrap();
+  assert_eq!(
+    output["machines"][0]["slots"][1]["item"]["name"],
+    "Cherry Coke"
+  );
+  assert_eq!(output["machines"][1]["is_online"], false);
+}
+
+#[test]
+fn lists_as_csv() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["list", "--format", "csv"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let lines: Vec<&str> = run.stdout.lines().collect();
+  assert_eq!(
+    lines[0],
+    "machine,machine_display_name,machine_online,slot,item_id,item_name,price,active,empty,count"
+  );
+  assert_eq!(lines[3], "bigdrink,Big Drink,true,3,3,Water,25,true,true,0");
+  assert_eq!(lines.len(), 5);
+}
+
+#[test]
+fn unauthorized_retries_with_a_fresh_token() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    "/drinks",
+    Response::json(401, json!({"error": "Unauthorized"})),
+  );
+  let run = Clink::new().run(&stand_in, &["list"]);
+  assert_eq!(run.code, 3, "{}", run.stderr);
+  assert!(run.stderr.contains("Unauthorized"));
+  assert_eq!(stand_in.requests_to("/drinks").len(), 2);
+  // The fresh token comes from the refresh token rather than logging in again
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+  let refreshes = stand_in.requests_to(common::TOKEN_PATH);
+  assert!(refreshes
+    .iter()
+    .any(|request| request.body.contains("grant_type=refresh_token")));
+}
+
+#[test]
+fn server_error() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    "/drinks",
+    Response::json(500, json!({"error": "Database is on fire"})),
+  );
+  let run = Clink::new().run(&stand_in, &["list"]);
+  assert_eq!(run.code, 1);
+  assert!(run.stderr.contains("Database is on fire"), "{}", run.stderr);
+}
+
+#[test]
+fn malformed_json() {
+  let stand_in = StandIn::start();
+  stand_in.respond("/drinks", Response::text(200, "{\"machines\": ["));
+  let run = Clink::new().run(&stand_in, &["list"]);
+  assert_eq!(run.code, 1);
+  assert!(run.stderr.contains("BadFormat"), "{}", run.stderr);
+}
+
+#[test]
+fn falls_back_to_the_last_list() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["list"]).code, 0);
+  stand_in.respond("/drinks", Response::text(503, "Service Unavailable"));
+  let run = clink.run(&stand_in, &["--retries", "0", "list", "bigdrink"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stderr.contains("may be stale"), "{}", run.stderr);
+  assert!(run.stdout.contains("Big Drink (bigdrink)"));
+  assert!(!run.stdout.contains("Little Drink"));
+}
+
+#[test]
+fn server_errors_arent_offline() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["list"]).code, 0);
+  stand_in.respond("/drinks", Response::text(500, "Internal Server Error"));
+  let run = clink.run(&stand_in, &["list"]);
+  assert_eq!(run.code, 1, "{}", run.stderr);
+  assert!(!run.stderr.contains("may be stale"), "{}", run.stderr);
+  assert!(run.stdout.is_empty());
+}
+
+#[test]
+fn lists_offline() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let run = clink.run(&stand_in, &["list", "--offline"]);
+  assert_eq!(run.code, 14);
+  assert!(stand_in.requests_to("/drinks").is_empty());
+  assert_eq!(clink.run(&stand_in, &["list"]).code, 0);
+  let run = clink.run(&stand_in, &["list", "--offline", "--format", "json"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stderr.contains("Offline, showing machines as of"));
+  let output: Value = serde_json::from_str(&run.stdout).unwrap();
+  assert_eq!(output["machines"][0]["name"], "bigdrink");
+  assert_eq!(stand_in.requests_to("/drinks").len(), 1);
+}
+
+#[test]
+fn doesnt_hide_real_errors() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["list"]).code, 0);
+  stand_in.respond("/drinks", Response::json(403, json!({"error": "Nope"})));
+  assert_eq!(clink.run(&stand_in, &["list"]).code, 8);
+}
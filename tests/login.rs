@@ -0,0 +1,86 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::json;
+use std::io::{BufRead, BufReader};
+use std::process::Stdio;
+
+#[test]
+fn logs_in_with_a_device_code() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().device_pending = 2;
+  let clink = Clink::new();
+  let run = clink.run(&stand_in, &["--login", "device", "token"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stderr.contains("enter WDJB-MJHT"), "{}", run.stderr);
+  let token = stand_in.state.lock().unwrap().token.clone();
+  assert_eq!(run.stdout, format!("Bearer {}\n", token));
+  assert_eq!(stand_in.requests_to(common::TOKEN_PATH).len(), 3);
+  assert!(stand_in.requests_to(common::AUTH_PATH).is_empty());
+  // Later runs use the cached token like any other
+  assert_eq!(
+    clink.run(&stand_in, &["--login", "device", "credits"]).code,
+    0
+  );
+  assert_eq!(stand_in.requests_to(common::DEVICE_PATH).len(), 1);
+}
+
+#[test]
+fn device_login_can_be_denied() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    common::TOKEN_PATH,
+    Response::json(
+      400,
+      json!({"error": "access_denied", "error_description": "The user denied the request"}),
+    ),
+  );
+  let run = Clink::new().run(&stand_in, &["--login", "device", "token"]);
+  assert_eq!(run.code, 11, "{}", run.stderr);
+  assert!(run.stderr.contains("denied"), "{}", run.stderr);
+}
+
+#[test]
+fn logs_in_with_a_browser() {
+  let stand_in = StandIn::start();
+  let mut child = Clink::new()
+    .command()
+    .args(["--api", &stand_in.url, "--oidc-issuer", &stand_in.issuer()])
+    .args(["--login", "pkce", "token"])
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let mut stderr = BufReader::new(child.stderr.take().unwrap());
+  let mut line = String::new();
+  stderr.read_line(&mut line).unwrap();
+  let url = line
+    .trim()
+    .strip_prefix("To log in, open ")
+    .and_then(|line| line.strip_suffix(" in a browser"))
+    .unwrap_or_else(|| panic!("{}", line));
+
+  // Play the browser: SSO sends us back to clink, which ignores anything but
+  // the callback it's waiting for
+  let redirect = common::get(url);
+  let callback = redirect.header("Location").unwrap();
+  let favicon = callback.split("/callback").next().unwrap().to_string() + "/favicon.ico";
+  assert_eq!(common::get(&favicon).status, 404);
+  let forged = callback.replace("state=", "state=forged");
+  assert_eq!(common::get(&forged).status, 400);
+  assert_eq!(common::get(callback).status, 200);
+
+  let output = child.wait_with_output().unwrap();
+  assert!(output.status.success());
+  let token = stand_in.state.lock().unwrap().token.clone();
+  assert_eq!(
+    String::from_utf8(output.stdout).unwrap(),
+    format!("Bearer {}\n", token)
+  );
+  let exchange = &stand_in.requests_to(common::TOKEN_PATH)[0];
+  assert!(
+    exchange.body.contains("code_verifier="),
+    "{}",
+    exchange.body
+  );
+}
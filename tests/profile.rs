@@ -0,0 +1,82 @@
+mod common;
+
+use common::Clink;
+use serde_json::Value;
+use std::fs;
+
+fn write_config(clink: &Clink) {
+  let dir = clink.home.path().join("config").join("clink");
+  fs::create_dir_all(&dir).unwrap();
+  fs::write(
+    dir.join("config.toml"),
+    "profile = \"dev\"\n\n[profiles.dev]\napi_url = \"https://drink-dev.example.com\"\n",
+  )
+  .unwrap();
+}
+
+#[test]
+fn lists_profiles() {
+  let clink = Clink::new();
+  write_config(&clink);
+  let output = clink.command().args(["profile", "list"]).output().unwrap();
+  assert!(output.status.success());
+  assert_eq!(
+    String::from_utf8_lossy(&output.stdout),
+    "  prod (https://drink.csh.rit.edu)\n* dev (https://drink-dev.example.com)\n"
+  );
+}
+
+#[test]
+fn shows_profiles() {
+  let clink = Clink::new();
+  write_config(&clink);
+  let output = clink
+    .command()
+    .args([
+      "--profile",
+      "prod",
+      "profile",
+      "show",
+      "dev",
+      "--format",
+      "json",
+    ])
+    .output()
+    .unwrap();
+  assert!(output.status.success());
+  let details: Value = serde_json::from_slice(&output.stdout).unwrap();
+  assert_eq!(details["name"], "dev");
+  assert_eq!(details["api_url"], "https://drink-dev.example.com");
+  assert!(details["token_cache"]
+    .as_str()
+    .unwrap()
+    .ends_with("token-dev.json"));
+}
+
+#[test]
+fn unknown_profiles_are_config_errors() {
+  let clink = Clink::new();
+  let output = clink
+    .command()
+    .args(["--profile", "nope", "profile", "show"])
+    .output()
+    .unwrap();
+  assert_eq!(output.status.code(), Some(12));
+}
+
+#[test]
+fn bad_proxies_only_break_commands_that_need_a_server() {
+  let clink = Clink::new();
+  let output = clink
+    .command()
+    .args(["--proxy", "not a url", "profile", "show"])
+    .output()
+    .unwrap();
+  assert!(output.status.success());
+  let output = clink
+    .command()
+    .args(["--proxy", "not a url", "credits"])
+    .output()
+    .unwrap();
+  assert_eq!(output.status.code(), Some(12));
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::json;
+
+fn busy(retry_after: &str) -> Response {
+  Response::json(503, json!({"error": "Try again later"})).with_header("Retry-After", retry_after)
+}
+
+#[test]
+fn retries_lookups() {
+  let stand_in = StandIn::start();
+  stand_in.respond_once("/drinks", busy("0"));
+  stand_in.respond_once("/drinks", busy("0"));
+  let run = Clink::new().run(&stand_in, &["list"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stdout.contains("Big Drink"));
+  assert_eq!(stand_in.requests_to("/drinks").len(), 3);
+}
+
+#[test]
+fn backs_off_without_retry_after() {
+  let stand_in = StandIn::start();
+  stand_in.respond_once("/drinks", Response::text(502, "Bad Gateway"));
+  let run = Clink::new().run(&stand_in, &["list"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(stand_in.requests_to("/drinks").len(), 2);
+}
+
+#[test]
+fn gives_up_eventually() {
+  let stand_in = StandIn::start();
+  stand_in.respond("/drinks", busy("0"));
+  let run = Clink::new().run(&stand_in, &["--retries", "2", "list"]);
+  assert_eq!(run.code, 1);
+  assert_eq!(stand_in.requests_to("/drinks").len(), 3);
+}
+
+#[test]
+fn wont_wait_forever() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    "/drinks",
+    Response::json(429, json!({"error": "Slow down"})).with_header("Retry-After", "3600"),
+  );
+  let run = Clink::new().run(&stand_in, &["list"]);
+  assert_eq!(run.code, 9);
+  assert!(run.stderr.contains("3600 seconds"), "{}", run.stderr);
+  assert_eq!(stand_in.requests_to("/drinks").len(), 1);
+}
+
+#[test]
+fn never_retries_drops() {
+  let stand_in = StandIn::start();
+  stand_in.respond_once("/drinks/drop", busy("0"));
+  let run = Clink::new().run(&stand_in, &["drop", "bigdrink", "1"]);
+  assert_ne!(run.code, 0);
+  assert_eq!(stand_in.requests_to("/drinks/drop").len(), 1);
+  assert_eq!(stand_in.balance(), 100);
+}
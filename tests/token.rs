@@ -0,0 +1,208 @@
+mod common;
+
+use common::{Clink, Response, StandIn};
+use serde_json::{json, Value};
+use std::fs;
+
+#[test]
+fn prints_the_token() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["token"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let token = stand_in.state.lock().unwrap().token.clone();
+  assert_eq!(run.stdout, format!("Bearer {}\n", token));
+}
+
+#[test]
+fn reuses_the_cached_token() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["token"]).code, 0);
+  assert_eq!(clink.run(&stand_in, &["credits"]).code, 0);
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+}
+
+#[test]
+fn unauthorized() {
+  let stand_in = StandIn::start();
+  stand_in.respond(
+    common::AUTH_PATH,
+    Response::redirect(
+      "drink://callback#error=unauthorized_client&error_description=Client+not+allowed",
+    ),
+  );
+  let run = Clink::new().run(&stand_in, &["token"]);
+  assert_eq!(run.code, 11, "{}", run.stderr);
+  assert!(run.stderr.contains("Client not allowed"), "{}", run.stderr);
+}
+
+#[test]
+fn server_error() {
+  let stand_in = StandIn::start();
+  stand_in.respond(common::AUTH_PATH, Response::text(500, "Internal error"));
+  let run = Clink::new().run(&stand_in, &["token"]);
+  assert_eq!(run.code, 11, "{}", run.stderr);
+}
+
+#[test]
+fn malformed_redirect() {
+  let stand_in = StandIn::start();
+  stand_in.respond(common::AUTH_PATH, Response::redirect("not a url"));
+  let run = Clink::new().run(&stand_in, &["token"]);
+  assert_eq!(run.code, 1, "{}", run.stderr);
+  assert!(run.stderr.contains("BadFormat"), "{}", run.stderr);
+}
+
+/// Makes the cached access token look expired, leaving its refresh token alone
+fn expire(clink: &Clink) {
+  let path = clink.token_cache();
+  let mut token: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+  token["expires_at"] = json!(1);
+  fs::write(&path, token.to_string()).unwrap();
+}
+
+fn grants(stand_in: &StandIn, grant_type: &str) -> usize {
+  stand_in
+    .requests_to(common::TOKEN_PATH)
+    .iter()
+    .filter(|request| request.body.contains(&format!("grant_type={}", grant_type)))
+    .count()
+}
+
+#[test]
+fn refreshes_expired_tokens() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["token"]).code, 0);
+  expire(&clink);
+  let run = clink.run(&stand_in, &["credits"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(grants(&stand_in, "refresh_token"), 1);
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+}
+
+#[test]
+fn refreshes_revoked_tokens() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["token"]).code, 0);
+  stand_in.state.lock().unwrap().token = common::fake_jwt("tester2");
+  let run = clink.run(&stand_in, &["credits"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(grants(&stand_in, "refresh_token"), 1);
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+}
+
+#[test]
+fn logs_in_again_when_refresh_is_rejected() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["token"]).code, 0);
+  expire(&clink);
+  stand_in.respond_once(
+    common::TOKEN_PATH,
+    Response::json(400, json!({"error": "invalid_grant"})),
+  );
+  let run = clink.run(&stand_in, &["credits"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 2);
+}
+
+#[test]
+fn falls_back_to_the_implicit_flow() {
+  let stand_in = StandIn::start();
+  let token = {
+    let mut state = stand_in.state.lock().unwrap();
+    state.implicit_only = true;
+    state.token.clone()
+  };
+  let clink = Clink::new();
+  let run = clink.run(&stand_in, &["token"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, format!("Bearer {}\n", token));
+  let requests = stand_in.requests_to(common::AUTH_PATH);
+  let response_types: Vec<_> = requests
+    .iter()
+    .map(|request| common::query_param(&request.query, "response_type").unwrap())
+    .collect();
+  assert_eq!(response_types, ["code", "token id_token"]);
+  assert!(stand_in.requests_to(common::TOKEN_PATH).is_empty());
+  let cached: Value = serde_json::from_slice(&fs::read(clink.token_cache()).unwrap()).unwrap();
+  assert!(cached.get("refresh_token").is_none());
+}
+
+#[test]
+fn uses_a_given_token() {
+  let stand_in = StandIn::start();
+  let token = stand_in.state.lock().unwrap().token.clone();
+  let clink = Clink::new();
+  let run: common::Run = clink
+    .command()
+    .env("CLINK_TOKEN", &token)
+    .args(["--api", &stand_in.url, "--oidc-issuer", &stand_in.issuer()])
+    .arg("credits")
+    .output()
+    .unwrap()
+    .into();
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(stand_in.requests_to(common::AUTH_PATH).is_empty());
+  assert!(!clink.token_cache().exists());
+}
+
+#[test]
+fn given_tokens_beat_the_cache() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  assert_eq!(clink.run(&stand_in, &["token"]).code, 0);
+  let run: common::Run = clink
+    .command()
+    .env("CLINK_TOKEN", "given")
+    .args(["--api", &stand_in.url, "--oidc-issuer", &stand_in.issuer()])
+    .arg("token")
+    .output()
+    .unwrap()
+    .into();
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(run.stdout, "Bearer given\n");
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+}
+
+#[test]
+fn missing_token_files_dont_skip_the_cache() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let path = clink.home.path().join("token");
+  let path = path.to_str().unwrap();
+  for _ in 0..2 {
+    let run = clink.run(&stand_in, &["--token-file", path, "token"]);
+    assert_eq!(run.code, 0, "{}", run.stderr);
+  }
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+}
+
+#[test]
+fn reads_tokens_from_a_file() {
+  let stand_in = StandIn::start();
+  let clink = Clink::new();
+  let path = clink.home.path().join("token");
+  let path = path.to_str().unwrap();
+  // Nothing there yet, so log in as usual
+  assert_eq!(
+    clink
+      .run(&stand_in, &["--token-file", path, "credits"])
+      .code,
+    0
+  );
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+
+  let clink = Clink::new();
+  let token = stand_in.state.lock().unwrap().token.clone();
+  fs::write(clink.home.path().join("token"), format!("{}\n", token)).unwrap();
+  let path = clink.home.path().join("token");
+  let run = clink.run(
+    &stand_in,
+    &["--token-file", path.to_str().unwrap(), "credits"],
+  );
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert_eq!(stand_in.requests_to(common::AUTH_PATH).len(), 1);
+}
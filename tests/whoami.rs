@@ -0,0 +1,44 @@
+mod common;
+
+use common::{Clink, StandIn, USERINFO_PATH};
+use serde_json::{json, Value};
+
+#[test]
+fn asks_the_sso_for_what_the_token_lacks() {
+  let stand_in = StandIn::start();
+  let run = Clink::new().run(&stand_in, &["whoami", "--format", "json"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  let mut output: Value = serde_json::from_str(&run.stdout).unwrap();
+  assert!(output["token_expires_at"].as_u64().unwrap() > 0);
+  output.as_object_mut().unwrap().remove("token_expires_at");
+  assert_eq!(
+    output,
+    json!({
+      "username": "tester",
+      "name": "Test User",
+      "email": "tester@csh.rit.edu",
+      "groups": ["member", "drink"],
+      "credits": 100,
+      "kerberos_principal": null,
+    })
+  );
+}
+
+#[test]
+fn reads_the_token_claims() {
+  let stand_in = StandIn::start();
+  stand_in.state.lock().unwrap().token = common::jwt(json!({
+    "preferred_username": "tester",
+    "name": "Token User",
+    "email": "token@csh.rit.edu",
+    "groups": ["rtp"],
+  }));
+  let run = Clink::new().run(&stand_in, &["whoami"]);
+  assert_eq!(run.code, 0, "{}", run.stderr);
+  assert!(run.stdout.starts_with(
+    "Username: tester\nName: Token User\nEmail: token@csh.rit.edu\nGroups: rtp\nCredits: 100\nToken expires: "
+  ));
+  assert!(run.stdout.ends_with("Kerberos principal: none\n"));
+  // The token had everything, so the SSO wasn't asked at all
+  assert!(stand_in.requests_to(USERINFO_PATH).is_empty());
+}